@@ -0,0 +1,571 @@
+use crate::config::ToolConfiguration;
+use crate::util::retry;
+use crate::{bail, Result};
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::Archive;
+
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::PermissionsExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArchiveType {
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+    #[serde(rename = "tar.xz", alias = "txz")]
+    TarXz,
+    #[serde(rename = "tar.bz2", alias = "tbz2", alias = "tbz")]
+    TarBz2,
+    #[serde(rename = "tar.zst", alias = "tzst")]
+    TarZst,
+    // single gzip compressed executable
+    #[serde(rename = "gz")]
+    Gz,
+    // single xz compressed executable
+    #[serde(rename = "xz")]
+    Xz,
+    // plain executable
+    #[serde(rename = "raw")]
+    Raw,
+}
+
+// Archive and compression formats that are recognized, but cannot be extracted
+const UNSUPPORTED_EXTENSIONS: &[&str] = &[
+    ".7z", ".rar", ".bz2", ".zst", ".lz", ".lzma", ".z", ".cab", ".dmg", ".pkg", ".msi", ".deb",
+    ".rpm",
+];
+
+// Single file artifacts, which are stored as is even if they are zip files like .jar
+const SINGLE_FILE_EXTENSIONS: &[&str] = &[
+    ".exe",
+    ".jar",
+    ".whl",
+    ".bin",
+    ".sh",
+    ".py",
+    ".phar",
+    ".pex",
+    ".appimage",
+    ".bat",
+    ".cmd",
+    ".ps1",
+];
+
+// Bytes of decompressed content needed to detect a tarball
+const HEADER_SIZE: usize = 512;
+
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+
+impl ArchiveType {
+    // Determine the archive type from the file name of the download url, returns None if the
+    // file name is ambiguous and the archive type needs to be detected from the content
+    pub fn from_file_name(file_name: &str) -> Result<Option<ArchiveType>> {
+        let file_name = file_name.to_lowercase();
+        let archive_types: &[(&[&str], ArchiveType)] = &[
+            (&[".zip"], ArchiveType::Zip),
+            (&[".tar"], ArchiveType::Tar),
+            (&[".tar.gz", ".tgz"], ArchiveType::TarGz),
+            (&[".tar.xz", ".txz"], ArchiveType::TarXz),
+            (&[".tar.bz2", ".tbz2", ".tbz"], ArchiveType::TarBz2),
+            (&[".tar.zst", ".tzst"], ArchiveType::TarZst),
+        ];
+        for (extensions, archive_type) in archive_types {
+            if extensions
+                .iter()
+                .any(|extension| file_name.ends_with(extension))
+            {
+                return Ok(Some(*archive_type));
+            }
+        }
+        if file_name.ends_with(".gz") || file_name.ends_with(".xz") {
+            // either a tarball or a single compressed file
+            return Ok(None);
+        }
+        if let Some(extension) = UNSUPPORTED_EXTENSIONS
+            .iter()
+            .find(|extension| file_name.ends_with(*extension))
+        {
+            bail!(
+                "Unsupported archive type '{}' for file {}",
+                extension,
+                file_name
+            );
+        }
+        if SINGLE_FILE_EXTENSIONS
+            .iter()
+            .any(|extension| file_name.ends_with(extension))
+        {
+            return Ok(Some(ArchiveType::Raw));
+        }
+        if let Some(extension) = get_extension(&file_name) {
+            bail!(
+                "Unknown archive type '.{}' for file {}, please configure the format of the tool",
+                extension,
+                file_name
+            );
+        }
+        Ok(None)
+    }
+
+    // Detect the archive type from the magic bytes at the start of the file
+    pub fn from_content(path: &Path) -> Result<ArchiveType> {
+        let mut header = Vec::new();
+        File::open(path)
+            .and_then(|file| file.take(HEADER_SIZE as u64).read_to_end(&mut header))
+            .with_context(|| format!("Unable to read file {:?} to detect archive type", path))?;
+        let archive_type = if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06")
+        {
+            ArchiveType::Zip
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            let mut decompressed = Vec::new();
+            // A truncated read is fine here, only the tar header is of interest
+            let _ = GzDecoder::new(File::open(path)?)
+                .take(HEADER_SIZE as u64)
+                .read_to_end(&mut decompressed);
+            if is_tar(&decompressed) {
+                ArchiveType::TarGz
+            } else {
+                ArchiveType::Gz
+            }
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            let mut decompressed = HeaderWriter { header: vec![] };
+            // The decompression is stopped by the writer once the tar header is available
+            let _ =
+                lzma_rs::xz_decompress(&mut BufReader::new(File::open(path)?), &mut decompressed);
+            if is_tar(&decompressed.header) {
+                ArchiveType::TarXz
+            } else {
+                ArchiveType::Xz
+            }
+        } else if header.starts_with(b"BZh") {
+            ArchiveType::TarBz2
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            ArchiveType::TarZst
+        } else if is_tar(&header) {
+            ArchiveType::Tar
+        } else {
+            ArchiveType::Raw
+        };
+        Ok(archive_type)
+    }
+}
+
+// Version numbers like foo-1.2.3 are not considered an extension
+fn get_extension(file_name: &str) -> Option<&str> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    if extension.chars().all(|c| c.is_ascii_alphanumeric())
+        && extension.chars().any(|c| c.is_ascii_alphabetic())
+    {
+        Some(extension)
+    } else {
+        None
+    }
+}
+
+// Keeps the start of the decompressed content and fails further writes to stop decompressing
+struct HeaderWriter {
+    header: Vec<u8>,
+}
+
+impl Write for HeaderWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.header.len() >= HEADER_SIZE {
+            return Err(std::io::Error::other("Header is complete"));
+        }
+        let length = buf.len().min(HEADER_SIZE - self.header.len());
+        self.header.extend_from_slice(&buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn is_tar(header: &[u8]) -> bool {
+    header.len() >= TAR_MAGIC_OFFSET + TAR_MAGIC.len()
+        && &header[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()] == TAR_MAGIC
+}
+
+pub fn extract(
+    archive_type: ArchiveType,
+    file_path: &Path,
+    tool: &ToolConfiguration,
+    extract_dir: &Path,
+) -> Result<()> {
+    match archive_type {
+        ArchiveType::Zip => extract_zip(file_path, tool, extract_dir),
+        ArchiveType::Tar => extract_tar(File::open(file_path)?, tool, extract_dir),
+        ArchiveType::TarGz => {
+            extract_tar(GzDecoder::new(File::open(file_path)?), tool, extract_dir)
+        }
+        ArchiveType::TarXz => {
+            // The xz decoder does not support streaming, so decompress to a temporary tar file first
+            let tar_path = file_path.with_extension("tar");
+            {
+                let mut input = BufReader::new(File::open(file_path)?);
+                let mut output = File::create(&tar_path)
+                    .with_context(|| format!("Unable to create file {:?}", tar_path))?;
+                lzma_rs::xz_decompress(&mut input, &mut output)
+                    .with_context(|| format!("Unable to decompress xz file {:?}", file_path))?;
+            }
+            extract_tar(File::open(&tar_path)?, tool, extract_dir)?;
+            retry(|| std::fs::remove_file(&tar_path))
+                .with_context(|| format!("Unable to remove temp file at {:?}", tar_path))
+        }
+        ArchiveType::TarBz2 => extract_tar(
+            bzip2::read::MultiBzDecoder::new(File::open(file_path)?),
+            tool,
+            extract_dir,
+        ),
+        ArchiveType::TarZst => {
+            let decoder =
+                ruzstd::decoding::StreamingDecoder::new(BufReader::new(File::open(file_path)?))
+                    .map_err(|error| anyhow::anyhow!("{}", error))
+                    .with_context(|| format!("Unable to open zstd file {:?}", file_path))?;
+            extract_tar(decoder, tool, extract_dir)
+        }
+        ArchiveType::Gz => extract_gz(file_path, tool, extract_dir),
+        ArchiveType::Xz => {
+            let raw_path = file_path.with_extension("raw");
+            {
+                let mut input = BufReader::new(File::open(file_path)?);
+                let mut output = File::create(&raw_path)
+                    .with_context(|| format!("Unable to create file {:?}", raw_path))?;
+                lzma_rs::xz_decompress(&mut input, &mut output)
+                    .with_context(|| format!("Unable to decompress xz file {:?}", file_path))?;
+            }
+            save_raw(&raw_path, tool, extract_dir)
+        }
+        ArchiveType::Raw => save_raw(file_path, tool, extract_dir),
+    }
+}
+
+fn extract_gz(file_path: &Path, tool: &ToolConfiguration, extract_dir: &Path) -> Result<()> {
+    let mut decoder = GzDecoder::new(File::open(file_path)?);
+    let mut raw_path = file_path.with_extension("raw");
+    {
+        let mut output = File::create(&raw_path)
+            .with_context(|| format!("Unable to create file {:?}", raw_path))?;
+        std::io::copy(&mut decoder, &mut output)
+            .with_context(|| format!("Unable to decompress gzip file {:?}", file_path))?;
+    }
+    // keep the exe extension of the original file name, so the executable can be found on windows
+    let original_name = decoder.header().and_then(|header| header.filename());
+    if let Some(original_name) = original_name {
+        if original_name.to_ascii_lowercase().ends_with(b".exe") {
+            let exe_path = raw_path.with_extension("exe");
+            retry(|| std::fs::rename(&raw_path, &exe_path)).with_context(|| {
+                format!("Unable to rename from {:?} to {:?}", raw_path, exe_path)
+            })?;
+            raw_path = exe_path;
+        }
+    }
+    save_raw(&raw_path, tool, extract_dir)
+}
+
+fn extract_zip(file_path: &Path, tool: &ToolConfiguration, extract_dir: &Path) -> Result<()> {
+    let file = File::open(file_path)?;
+    let mut archive = zip::ZipArchive::new(file)
+        .with_context(|| format!("Unable to open zip file {:?}", file_path))?;
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Unable to open zip entry {:?}", i))?;
+        let file_name: PathBuf = strip_filename(file.sanitized_name(), tool, file.is_dir())?;
+        let outpath = extract_dir.join(file_name);
+
+        if file.name().ends_with('/') {
+            std::fs::create_dir_all(&outpath).unwrap();
+        } else {
+            if let Some(p) = outpath.parent() {
+                if !p.exists() {
+                    std::fs::create_dir_all(p)
+                        .with_context(|| format!("Unable to zip path {:?}", p))?;
+                }
+            }
+            let mut outfile = std::fs::File::create(&outpath)
+                .with_context(|| format!("Could not create output file '{:?}'", outpath))?;
+            std::io::copy(&mut file, &mut outfile).with_context(|| {
+                format!("Unable to extract file {:?} to path {:?}", outpath, outfile)
+            })?;
+            #[cfg(target_family = "unix")]
+            {
+                // Set linux file permission to make files executable
+                if let Some(mode) = file.unix_mode() {
+                    let mut perms = std::fs::metadata(&outpath)?.permissions();
+                    perms.set_mode(mode);
+                    std::fs::set_permissions(outpath, perms)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn save_raw(file_path: &Path, tool: &ToolConfiguration, extract_dir: &Path) -> Result<()> {
+    // save as tool name
+    let from = file_path.as_os_str();
+    #[cfg(target_family = "unix")]
+    {
+        let mut perms = std::fs::metadata(file_path)?.permissions();
+        perms.set_mode(0o755);
+        std::fs::set_permissions(from, perms)?;
+    }
+    let extension = file_path.extension().and_then(|x| x.to_str());
+    let mut filename = tool.name.to_string();
+    if let Some(extension) = extension {
+        if extension == "exe" {
+            filename += ".";
+            filename += extension;
+        }
+    }
+    let to = extract_dir.join(filename);
+    retry(|| std::fs::rename(from, &to))
+        .with_context(|| format!("Unable to rename from {:?} to {:?}", from, to))?;
+    Ok(())
+}
+
+fn extract_tar<R: Read>(reader: R, tool: &ToolConfiguration, extract_dir: &Path) -> Result<()> {
+    let root = dunce::canonicalize(extract_dir)?;
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_path_buf();
+        check_archive_path(&entry_path)
+            .with_context(|| format!("Refusing to extract tar entry {:?}", entry_path))?;
+        let entry_type = entry.header().entry_type();
+        let path: PathBuf = strip_filename(entry_path.clone(), tool, entry_type.is_dir())?;
+        if path.components().next().is_none() {
+            // stripped root directory, already created
+            continue;
+        }
+        let outpath = extract_dir.join(path);
+        let parent = outpath.parent().expect("parent");
+        std::fs::create_dir_all(parent)?;
+        // Resolve the parent directory to detect writes through symlinks pointing outside
+        let parent = dunce::canonicalize(parent)?;
+        let parent_in_root = match parent.strip_prefix(&root) {
+            Ok(parent_in_root) => parent_in_root.to_path_buf(),
+            Err(_) => bail!(
+                "Refusing to extract tar entry {:?}: it would be written outside the extraction directory",
+                entry_path
+            ),
+        };
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let link_name = entry
+                .link_name()?
+                .with_context(|| format!("Tar entry {:?} has no link target", entry_path))?
+                .to_path_buf();
+            if entry_type.is_symlink() {
+                check_symlink_target(&parent_in_root, &link_name).with_context(|| {
+                    format!(
+                        "Refusing to extract tar entry {:?} linking to {:?}",
+                        entry_path, link_name
+                    )
+                })?;
+            } else {
+                // Hard link targets are relative to the archive root and need to be stripped as well
+                check_archive_path(&link_name).with_context(|| {
+                    format!(
+                        "Refusing to extract tar entry {:?} linking to {:?}",
+                        entry_path, link_name
+                    )
+                })?;
+                let target = extract_dir.join(strip_filename(link_name.clone(), tool, false)?);
+                let target_in_root = dunce::canonicalize(&target)
+                    .map(|target| target.starts_with(&root))
+                    .unwrap_or(false);
+                if !target_in_root {
+                    bail!(
+                        "Refusing to extract tar entry {:?} linking to {:?}: link target is outside the extraction directory",
+                        entry_path,
+                        link_name
+                    );
+                }
+                std::fs::hard_link(&target, &outpath).with_context(|| {
+                    format!(
+                        "Could not create hard link '{:?}' to '{:?}'",
+                        outpath, target
+                    )
+                })?;
+                continue;
+            }
+        }
+        entry
+            .unpack(&outpath)
+            .with_context(|| format!("Could not create output file '{:?}'", outpath))?;
+    }
+    Ok(())
+}
+
+// Archive paths must be relative and may not point to parent directories
+fn check_archive_path(path: &Path) -> Result<()> {
+    for component in path.components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {}
+            Component::ParentDir => bail!("path contains '..'"),
+            Component::RootDir | Component::Prefix(_) => bail!("path is absolute"),
+        }
+    }
+    Ok(())
+}
+
+// Symlink targets must be relative and resolve to a location inside the extraction directory.
+// Parent directory components are only allowed at the start of the target, so that they cannot
+// be used to traverse out of the extraction directory via another symlink.
+fn check_symlink_target(link_dir: &Path, target: &Path) -> Result<()> {
+    let mut depth = link_dir.components().count();
+    let mut seen_normal_component = false;
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => seen_normal_component = true,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if seen_normal_component {
+                    bail!("link target contains '..' after a directory name");
+                }
+                if depth == 0 {
+                    bail!("link target is outside the extraction directory");
+                }
+                depth -= 1;
+            }
+            Component::RootDir | Component::Prefix(_) => bail!("link target is absolute"),
+        }
+    }
+    Ok(())
+}
+
+fn strip_filename(file_name: PathBuf, tool: &ToolConfiguration, is_dir: bool) -> Result<PathBuf> {
+    let new_file_name: PathBuf = file_name
+        .components()
+        .skip(tool.strip_directories)
+        .collect();
+    if !is_dir && new_file_name.components().next().is_none() {
+        bail!("File name {:?} was empty after stripping {} path components (in {:?}).\nHINT: Try setting strip_components: 0 in the tool configuration for {}", file_name, tool.strip_directories, tool.name, tool.name);
+    }
+    Ok(new_file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn archive_types() {
+        let cases = [
+            ("foo.zip", Some(ArchiveType::Zip)),
+            ("foo.tar", Some(ArchiveType::Tar)),
+            ("foo.tar.gz", Some(ArchiveType::TarGz)),
+            ("foo.tgz", Some(ArchiveType::TarGz)),
+            ("foo.TAR.XZ", Some(ArchiveType::TarXz)),
+            ("foo.txz", Some(ArchiveType::TarXz)),
+            ("foo.tar.bz2", Some(ArchiveType::TarBz2)),
+            ("foo.tbz2", Some(ArchiveType::TarBz2)),
+            ("foo.tar.zst", Some(ArchiveType::TarZst)),
+            ("foo.tzst", Some(ArchiveType::TarZst)),
+            ("foo.tbz", Some(ArchiveType::TarBz2)),
+            ("foo.exe", Some(ArchiveType::Raw)),
+            ("foo.jar", Some(ArchiveType::Raw)),
+            ("foo-1.2.3.whl", Some(ArchiveType::Raw)),
+            ("foo.gz", None),
+            ("foo.xz", None),
+            ("foo", None),
+            ("foo-1.2.3", None),
+        ];
+        for (file_name, archive_type) in &cases {
+            assert_eq!(
+                ArchiveType::from_file_name(file_name).unwrap(),
+                *archive_type,
+                "{}",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn archive_type_aliases() {
+        for (format, archive_type) in &[
+            ("tgz", ArchiveType::TarGz),
+            ("txz", ArchiveType::TarXz),
+            ("tbz2", ArchiveType::TarBz2),
+            ("tbz", ArchiveType::TarBz2),
+            ("tzst", ArchiveType::TarZst),
+        ] {
+            assert_eq!(
+                serde_yaml::from_str::<ArchiveType>(format).unwrap(),
+                *archive_type,
+                "{}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_archive_types() {
+        for file_name in &["foo.7z", "foo.rar", "foo.msi", "foo.rpm", "foo.arj"] {
+            ArchiveType::from_file_name(file_name).expect_err(file_name);
+        }
+    }
+
+    fn detect_archive_type(content: &[u8]) -> ArchiveType {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), content).unwrap();
+        ArchiveType::from_content(file.path()).unwrap()
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn xz(content: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut std::io::Cursor::new(content), &mut compressed).unwrap();
+        compressed
+    }
+
+    fn create_tar() -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_path("foo/hello_world.txt").unwrap();
+        header.set_size(13);
+        header.set_cksum();
+        ar.append(&header, &b"Hello, World!"[..]).unwrap();
+        ar.into_inner().unwrap()
+    }
+
+    #[test]
+    fn detect_archive_types() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("foo", Default::default()).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        assert_eq!(detect_archive_type(&zip), ArchiveType::Zip);
+        assert_eq!(detect_archive_type(&create_tar()), ArchiveType::Tar);
+        assert_eq!(
+            detect_archive_type(&gzip(&create_tar())),
+            ArchiveType::TarGz
+        );
+        assert_eq!(detect_archive_type(&gzip(b"#!/bin/sh")), ArchiveType::Gz);
+        assert_eq!(detect_archive_type(&xz(&create_tar())), ArchiveType::TarXz);
+        assert_eq!(detect_archive_type(&xz(b"#!/bin/sh")), ArchiveType::Xz);
+        assert_eq!(detect_archive_type(b"BZh91AY&SY"), ArchiveType::TarBz2);
+        assert_eq!(
+            detect_archive_type(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            ArchiveType::TarZst
+        );
+        assert_eq!(detect_archive_type(b"\x7fELF"), ArchiveType::Raw);
+        assert_eq!(detect_archive_type(b""), ArchiveType::Raw);
+    }
+}
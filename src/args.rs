@@ -4,11 +4,38 @@ use anyhow::Context;
 #[derive(Debug, PartialEq, Eq)]
 pub enum Args {
     Help,
-    Download,
+    Download {
+        frozen: bool,
+        offline: bool,
+        // tool names or tags to download, all tools if empty
+        tool_names: Vec<String>,
+    },
+    Lock {
+        offline: bool,
+    },
+    Verify {
+        offline: bool,
+    },
+    Reinstall {
+        tool_names: Vec<String>,
+        all: bool,
+        offline: bool,
+    },
+    Clean {
+        all: bool,
+        shared: bool,
+    },
+    Check,
     Invocation(Invocation),
-    GetBinaryPath { command_name: String },
-    GetToolPath { tool_name: String },
-    GetToolVersion { tool_name: String },
+    GetBinaryPath {
+        command_name: String,
+    },
+    GetToolPath {
+        tool_name: String,
+    },
+    GetToolVersion {
+        tool_name: String,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -16,6 +43,8 @@ pub struct Invocation {
     pub command_name: String,
     pub verbose: bool,
     pub from_shim: bool,
+    pub frozen: bool,
+    pub offline: bool,
     pub args: Vec<String>,
 }
 
@@ -23,11 +52,74 @@ pub fn parse_args(args: &mut dyn Iterator<Item = String>, verbose_env: bool) ->
     if let Some(mut command) = args.next() {
         let mut verbose = verbose_env;
         let mut from_shim = false;
+        let mut frozen = false;
+        let mut offline = false;
         if &command == "--help" {
             return Ok(Args::Help);
         }
         if &command == "--download" {
-            return Ok(Args::Download);
+            let mut tool_names = vec![];
+            for arg in args {
+                match arg.as_str() {
+                    "--frozen" => frozen = true,
+                    "--offline" => offline = true,
+                    arg if arg.starts_with('-') => {
+                        anyhow::bail!("tt: Unexpected argument '{}' for --download", arg)
+                    }
+                    _ => tool_names.push(arg),
+                }
+            }
+            return Ok(Args::Download {
+                frozen,
+                offline,
+                tool_names,
+            });
+        }
+        if &command == "--lock" {
+            let offline = parse_offline_flag(&command, args)?;
+            return Ok(Args::Lock { offline });
+        }
+        if &command == "--verify" {
+            let offline = parse_offline_flag(&command, args)?;
+            return Ok(Args::Verify { offline });
+        }
+        if &command == "--check" {
+            return Ok(Args::Check);
+        }
+        if &command == "--reinstall" {
+            let mut tool_names = vec![];
+            let mut all = false;
+            let mut offline = false;
+            for arg in args {
+                match arg.as_str() {
+                    "--all" => all = true,
+                    "--offline" => offline = true,
+                    arg if arg.starts_with('-') => {
+                        anyhow::bail!("tt: Unexpected argument '{}' for --reinstall", arg)
+                    }
+                    _ => tool_names.push(arg),
+                }
+            }
+            if tool_names.is_empty() && !all {
+                anyhow::bail!("tt: Expected a tool name or --all for --reinstall");
+            }
+            return Ok(Args::Reinstall {
+                tool_names,
+                all,
+                offline,
+            });
+        }
+        if &command == "--clean" {
+            let mut all = false;
+            let mut shared = false;
+            for arg in args {
+                match arg.as_str() {
+                    "--all" => all = true,
+                    "--shared" => shared = true,
+                    arg => anyhow::bail!("tt: Unexpected argument '{}' for --clean", arg),
+                }
+            }
+            return Ok(Args::Clean { all, shared });
         }
         if &command == "--getBinaryPath" {
             return Ok(Args::GetBinaryPath {
@@ -67,6 +159,20 @@ pub fn parse_args(args: &mut dyn Iterator<Item = String>, verbose_env: bool) ->
                     from_shim = true;
                     command = rest_args.remove(0);
                 }
+                "--frozen" => {
+                    if rest_args.is_empty() {
+                        anyhow::bail!("tt: No command given")
+                    }
+                    frozen = true;
+                    command = rest_args.remove(0);
+                }
+                "--offline" => {
+                    if rest_args.is_empty() {
+                        anyhow::bail!("tt: No command given")
+                    }
+                    offline = true;
+                    command = rest_args.remove(0);
+                }
                 _ => break,
             }
         }
@@ -74,12 +180,26 @@ pub fn parse_args(args: &mut dyn Iterator<Item = String>, verbose_env: bool) ->
             command_name: command,
             verbose,
             from_shim,
+            frozen,
+            offline,
             args: rest_args,
         }));
     }
     Ok(Args::Help)
 }
 
+// Parse the arguments of commands which only accept --offline
+fn parse_offline_flag(command: &str, args: &mut dyn Iterator<Item = String>) -> Result<bool> {
+    let mut offline = false;
+    for arg in args {
+        match arg.as_str() {
+            "--offline" => offline = true,
+            arg => anyhow::bail!("tt: Unexpected argument '{}' for {}", arg, command),
+        }
+    }
+    Ok(offline)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,7 +224,145 @@ mod tests {
 
     #[test]
     fn parse_download() {
-        assert_eq!(test_args(&["--download"], false), Args::Download);
+        assert_eq!(
+            test_args(&["--download"], false),
+            Args::Download {
+                frozen: false,
+                offline: false,
+                tool_names: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn parse_download_frozen() {
+        assert_eq!(
+            test_args(&["--download", "--frozen"], false),
+            Args::Download {
+                frozen: true,
+                offline: false,
+                tool_names: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn parse_download_offline() {
+        assert_eq!(
+            test_args(&["--download", "--offline"], false),
+            Args::Download {
+                frozen: false,
+                offline: true,
+                tool_names: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn parse_download_tools() {
+        assert_eq!(
+            test_args(&["--download", "foo", "--frozen", "backend"], false),
+            Args::Download {
+                frozen: true,
+                offline: false,
+                tool_names: make_args(&["foo", "backend"])
+            }
+        );
+    }
+
+    #[test]
+    fn parse_download_unexpected_argument() {
+        let error = parse_args(
+            &mut ["--download", "--thawed"].iter().map(|x| x.to_string()),
+            false,
+        )
+        .expect_err("Unexpected argument");
+        assert_eq!(
+            error.to_string(),
+            "tt: Unexpected argument '--thawed' for --download"
+        );
+    }
+
+    #[test]
+    fn parse_lock() {
+        assert_eq!(test_args(&["--lock"], false), Args::Lock { offline: false });
+        let error = parse_args(
+            &mut ["--lock", "--frozen"].iter().map(|x| x.to_string()),
+            false,
+        )
+        .expect_err("Unexpected argument");
+        assert_eq!(
+            error.to_string(),
+            "tt: Unexpected argument '--frozen' for --lock"
+        );
+    }
+
+    #[test]
+    fn parse_verify() {
+        assert_eq!(
+            test_args(&["--verify"], false),
+            Args::Verify { offline: false }
+        );
+        assert_eq!(
+            test_args(&["--verify", "--offline"], false),
+            Args::Verify { offline: true }
+        );
+    }
+
+    #[test]
+    fn parse_check() {
+        assert_eq!(test_args(&["--check"], false), Args::Check);
+    }
+
+    #[test]
+    fn parse_reinstall() {
+        assert_eq!(
+            test_args(&["--reinstall", "foo", "bar"], false),
+            Args::Reinstall {
+                tool_names: make_args(&["foo", "bar"]),
+                all: false,
+                offline: false
+            }
+        );
+        assert_eq!(
+            test_args(&["--reinstall", "--offline", "--all"], false),
+            Args::Reinstall {
+                tool_names: vec![],
+                all: true,
+                offline: true
+            }
+        );
+        let error = parse_args(&mut ["--reinstall"].iter().map(|x| x.to_string()), false)
+            .expect_err("Missing tool name");
+        assert_eq!(
+            error.to_string(),
+            "tt: Expected a tool name or --all for --reinstall"
+        );
+    }
+
+    #[test]
+    fn parse_clean() {
+        assert_eq!(
+            test_args(&["--clean"], false),
+            Args::Clean {
+                all: false,
+                shared: false
+            }
+        );
+        assert_eq!(
+            test_args(&["--clean", "--all"], false),
+            Args::Clean {
+                all: true,
+                shared: false
+            }
+        );
+        assert_eq!(
+            test_args(&["--clean", "--shared", "--all"], false),
+            Args::Clean {
+                all: true,
+                shared: true
+            }
+        );
     }
 
     #[test]
@@ -145,6 +403,8 @@ mod tests {
                 command_name: "shake".to_string(),
                 verbose: false,
                 from_shim: false,
+                frozen: false,
+                offline: false,
                 args: vec![],
             })
         );
@@ -158,6 +418,8 @@ mod tests {
                 command_name: "stir".to_string(),
                 verbose: false,
                 from_shim: false,
+                frozen: false,
+                offline: false,
                 args: make_args(&["--rotations", "42"]),
             })
         );
@@ -171,6 +433,8 @@ mod tests {
                 command_name: "foo".to_string(),
                 verbose: true,
                 from_shim: false,
+                frozen: false,
+                offline: false,
                 args: make_args(&["bar"]),
             })
         );
@@ -184,6 +448,38 @@ mod tests {
                 command_name: "foo".to_string(),
                 verbose: true,
                 from_shim: false,
+                frozen: false,
+                offline: false,
+                args: make_args(&["bar"]),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_command_frozen() {
+        assert_eq!(
+            test_args(&["--frozen", "foo", "bar"], false),
+            Args::Invocation(Invocation {
+                command_name: "foo".to_string(),
+                verbose: false,
+                from_shim: false,
+                frozen: true,
+                offline: false,
+                args: make_args(&["bar"]),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_command_offline() {
+        assert_eq!(
+            test_args(&["--offline", "-v", "foo", "bar"], false),
+            Args::Invocation(Invocation {
+                command_name: "foo".to_string(),
+                verbose: true,
+                from_shim: false,
+                frozen: false,
+                offline: true,
                 args: make_args(&["bar"]),
             })
         );
@@ -197,6 +493,8 @@ mod tests {
                 command_name: "foo".to_string(),
                 verbose: false,
                 from_shim: true,
+                frozen: false,
+                offline: false,
                 args: make_args(&["bar"]),
             })
         );
@@ -1,28 +1,50 @@
+use crate::archive::{self, ArchiveType};
+use crate::checksum::{sha256_file, sha256_hex, verify_sha256};
 use crate::config::{Configuration, ToolConfiguration};
-use crate::download::download;
+use crate::credentials::Credentials;
+use crate::download::{download, file_url, DownloadOptions};
+use crate::lockfile::{
+    get_lockfile_path, read_lockfile, LockedArtifact, LockedTool, Lockfile, LOCK_FILENAME,
+};
+use crate::manifest::{read_manifest, write_manifest, Manifest};
 use crate::platform::{Platform, PlatformFns, PlatformFunctions};
-use crate::template::template;
+use crate::progress::{format_bytes, ProgressReporter};
+use crate::proxy::ProxySettings;
+use crate::signature::verify_signature;
+use crate::store::{link_tree, SharedStore};
+use crate::template::{get_template_names, template};
 use crate::{make_absolute, Result};
 use anyhow::bail;
 use anyhow::Context;
-use flate2::read::GzDecoder;
-use std::collections::HashMap;
-use std::ffi::OsStr;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::fs::File;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::ops::Deref;
-use std::path::PathBuf;
-use tar::Archive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use crate::util::retry;
-#[cfg(target_family = "unix")]
-use std::os::unix::fs::PermissionsExt;
+use crate::util::{
+    find_tmp_dirs, get_tmp_dir_lock_path, lock_file, lock_tmp_dir, remove_tmp_dir, retry,
+    try_lock_file,
+};
+
+const DEFAULT_DOWNLOAD_WORKERS: usize = 4;
+const DEFAULT_LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+// number of damaged files listed when verifying a tool
+const MAX_REPORTED_PROBLEMS: usize = 5;
+// number of hex digits of the url hash in the names of partial downloads
+const PARTIAL_URL_HASH_LENGTH: usize = 16;
 
 pub struct Cache {
     pub configuration: Configuration,
     tools_dir: PathBuf,
-    platform: Box<dyn Platform>,
+    platform: Box<dyn Platform + Send + Sync>,
+    lockfile: Option<Lockfile>,
+    // refuse to download anything not matching the lockfile
+    pub frozen: bool,
+    // fail instead of downloading missing tools
+    pub offline: bool,
+    pub shared_store: Option<SharedStore>,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -43,6 +65,41 @@ impl fmt::Display for CommandNotFoundError {
     }
 }
 
+// Templates supported in commands and env values
+#[derive(Debug, Eq, PartialEq)]
+pub enum CommandTemplate<'a> {
+    // ${dir}, the directory of the tool
+    Dir,
+    // ${version}
+    Version,
+    // ${cmd:<command>}, the binary of another command
+    Command(&'a str),
+    // ${env:<variable>}
+    Env(&'a str),
+    // ${dir:<tool>}, the directory of another tool
+    ToolDir(&'a str),
+    // ${linux:<text>} and ${windows:<text>}, the text is only used on the given platform
+    Platform(&'a str, &'a str),
+}
+
+impl<'a> CommandTemplate<'a> {
+    pub fn parse(name: &'a str) -> Option<CommandTemplate<'a>> {
+        match name {
+            "dir" => return Some(CommandTemplate::Dir),
+            "version" => return Some(CommandTemplate::Version),
+            _ => {}
+        }
+        let (prefix, rest) = name.split_once(':')?;
+        match prefix {
+            "cmd" => Some(CommandTemplate::Command(rest)),
+            "env" => Some(CommandTemplate::Env(rest)),
+            "dir" => Some(CommandTemplate::ToolDir(rest)),
+            "linux" | "windows" => Some(CommandTemplate::Platform(prefix, rest)),
+            _ => None,
+        }
+    }
+}
+
 impl Cache {
     pub fn create(configuration: Configuration) -> Result<Self> {
         for configuration_file in &configuration.configuration_files {
@@ -52,161 +109,1041 @@ impl Cache {
         verbose!("Using cache_dir {}", cache_dir);
         let cache_dir = PathBuf::from(cache_dir);
         let tools_dir = cache_dir.join("tools");
+        let lockfile = match get_lockfile_path(&configuration) {
+            Some(lockfile_path) if lockfile_path.exists() => {
+                verbose!("Using lockfile {:?}", lockfile_path);
+                Some(read_lockfile(&lockfile_path)?)
+            }
+            _ => None,
+        };
+        let shared_store = configuration
+            .shared_cache_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(SharedStore::default_location)
+            .map(SharedStore::new);
         Ok(Cache {
             configuration,
             tools_dir,
             platform: Box::new(PlatformFns {}),
+            lockfile,
+            frozen: false,
+            offline: false,
+            shared_store,
         })
     }
     pub fn init(&mut self) -> Result<()> {
-        let tools_dir = &self.tools_dir;
-        let configuration = &self.configuration;
-        let tmp_dir = tools_dir.join(format!(
-            ".tmp/{}-{}",
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_nanos(),
-            std::process::id()
-        ));
+        self.install_tools(self.configuration.tools.iter().collect(), false)
+    }
 
-        for tool in &configuration.tools {
-            let tool_dir = self.get_tool_dir(tool);
-            if tool_dir.exists() {
+    // Install the tools with the given names or tags, including the tools their commands need
+    pub fn init_for_tools(&mut self, names: &[String]) -> Result<()> {
+        for name in names {
+            if !self
+                .configuration
+                .tools
+                .iter()
+                .any(|tool| &tool.name == name || tool.tags.contains(name))
+            {
+                bail!("Tool or tag '{}' not found", name);
+            }
+        }
+        let mut required_tools: Vec<&ToolConfiguration> = vec![];
+        for tool in &self.configuration.tools {
+            if !names
+                .iter()
+                .any(|name| &tool.name == name || tool.tags.contains(name))
+            {
+                continue;
+            }
+            let mut tools = vec![tool];
+            for command in tool.commands.keys() {
+                tools.extend(self.get_required_tools(command));
+            }
+            for tool in tools {
+                self.add_required_tool(tool, &mut required_tools);
+            }
+        }
+        self.install_tools(required_tools, false)
+    }
+
+    // Install only the tools needed to run the command
+    pub fn init_for_command(&mut self, command: &str) -> Result<()> {
+        self.install_tools(self.get_required_tools(command), false)
+    }
+
+    // The tool providing the command and the tools it references via ${cmd:...} and ${dir:...}
+    fn get_required_tools(&self, command: &str) -> Vec<&ToolConfiguration> {
+        let mut required_tools: Vec<&ToolConfiguration> = vec![];
+        let mut pending_commands = vec![command.to_string()];
+        let mut visited_commands = HashSet::new();
+        while let Some(command) = pending_commands.pop() {
+            if !visited_commands.insert(command.clone()) {
+                continue;
+            }
+            let tool = match self.find_command_tool(&command) {
+                Some(tool) => tool,
+                // unknown commands are reported when building the command line
+                None => continue,
+            };
+            self.add_required_tool(tool, &mut required_tools);
+            let templates = std::iter::once(&tool.commands[&command]).chain(tool.env.values());
+            for name in templates.flat_map(|string| get_template_names(string).unwrap_or_default())
+            {
+                match CommandTemplate::parse(&name) {
+                    Some(CommandTemplate::Command(command_name)) => {
+                        pending_commands.push(command_name.to_string())
+                    }
+                    Some(CommandTemplate::ToolDir(tool_name)) => {
+                        if let Some(tool) = self.get_tool(tool_name) {
+                            self.add_required_tool(tool, &mut required_tools);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        required_tools
+    }
+
+    // Add the tool together with the tools it requires
+    fn add_required_tool<'a>(
+        &'a self,
+        tool: &'a ToolConfiguration,
+        required_tools: &mut Vec<&'a ToolConfiguration>,
+    ) {
+        if required_tools.contains(&tool) {
+            return;
+        }
+        required_tools.push(tool);
+        for required in &tool.requires {
+            if let Some(required) = self.get_tool(required) {
+                self.add_required_tool(required, required_tools);
+            }
+        }
+    }
+
+    fn get_tool(&self, name: &str) -> Option<&ToolConfiguration> {
+        self.configuration
+            .tools
+            .iter()
+            .find(|tool| tool.name == name)
+    }
+
+    fn find_command_tool(&self, command: &str) -> Option<&ToolConfiguration> {
+        self.configuration
+            .tools
+            .iter()
+            .find(|tool| tool.commands.contains_key(command))
+    }
+
+    // Install the tools which are missing, or all of them if existing installations are replaced
+    fn install_tools(&self, tools: Vec<&ToolConfiguration>, replace: bool) -> Result<()> {
+        let pending_tools: Vec<&ToolConfiguration> = tools
+            .into_iter()
+            .filter(|tool| {
+                let exists = !replace && self.get_tool_dir(tool).exists();
+                if exists {
+                    verbose!(
+                        "Tool found, skipping download for {} v{}",
+                        tool.name,
+                        tool.version
+                    );
+                }
+                !exists
+            })
+            .collect();
+        let missing_tools: Vec<&&ToolConfiguration> = pending_tools
+            .iter()
+            .filter(|tool| self.find_in_store(tool).is_none())
+            .collect();
+        if self.offline && !missing_tools.is_empty() {
+            let missing_tools: Vec<String> = missing_tools
+                .iter()
+                .map(|tool| format!("  {} {}", tool.name, tool.version))
+                .collect();
+            // installed tools are only replaced once the new installation is ready, so nothing
+            // has been removed yet
+            if replace {
+                bail!("Unable to reinstall tools in offline mode, since they are not in the shared cache:\n{}", missing_tools.join("\n"));
+            }
+            bail!("Unable to install missing tools in offline mode:\n{}\nHINT: Run 'tt --download' with network access to install them", missing_tools.join("\n"));
+        }
+        let (tmp_dir, tmp_dir_lock) = self.get_tmp_dir()?;
+        // required tools are installed in an earlier stage than the tools requiring them
+        for stage in get_install_stages(&pending_tools) {
+            self.install_in_parallel(&stage, &tmp_dir, replace)?;
+        }
+        remove_tmp_dir(&tmp_dir, tmp_dir_lock)
+    }
+
+    // Install the tools using a pool of download workers
+    fn install_in_parallel(
+        &self,
+        tools: &[&ToolConfiguration],
+        tmp_dir: &Path,
+        replace: bool,
+    ) -> Result<()> {
+        let workers = self
+            .configuration
+            .download_workers
+            .unwrap_or(DEFAULT_DOWNLOAD_WORKERS)
+            .clamp(1, tools.len().max(1));
+        let parallel = workers > 1;
+        let next_tool = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let mut results: Vec<(usize, Result<()>)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = vec![];
+                        // stop picking up new tools once an installation failed
+                        while !failed.load(Ordering::Relaxed) {
+                            let index = next_tool.fetch_add(1, Ordering::Relaxed);
+                            let tool = match tools.get(index) {
+                                Some(tool) => tool,
+                                None => break,
+                            };
+                            let result = self.install_tool(tool, tmp_dir, parallel, replace);
+                            if result.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            results.push((index, result));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("download worker panicked"))
+                .collect()
+        });
+        results.sort_by_key(|(index, _)| *index);
+        let mut errors = results.into_iter().filter_map(|(_, result)| result.err());
+        if let Some(error) = errors.next() {
+            for other_error in errors {
+                report!("{:#}", other_error);
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Download, verify and extract a single tool, then move it into the tools dir
+    fn install_tool(
+        &self,
+        tool: &ToolConfiguration,
+        tmp_dir: &Path,
+        parallel: bool,
+        replace: bool,
+    ) -> Result<()> {
+        let tool_dir = self.get_tool_dir(tool);
+        std::fs::create_dir_all(tmp_dir)
+            .with_context(|| format!("Unable to create tmp dir {:?}", tmp_dir))?;
+        std::fs::create_dir_all(tool_dir.parent().expect("Parent should exist"))?;
+        // concurrent invocations wait for the first one to install the tool
+        let _lock = self.lock_tool(tool)?;
+        if tool_dir.exists() && !replace {
+            verbose!(
+                "{} {} was installed by another process",
+                tool.name,
+                tool.version
+            );
+            return Ok(());
+        }
+        // the primary url identifies the artifact in the lockfile, regardless of the mirror used
+        let url = self
+            .platform
+            .get_download_urls(tool)
+            .and_then(|urls| urls.first())
+            .with_context(|| format!("No download url configured for {}", tool.name))?;
+        if self.frozen && self.get_locked_artifact(tool, url).is_none() {
+            bail!("Refusing to download {} {} from {} in frozen mode, since it is not recorded in {}\nHINT: Run 'tt --lock' to update the lockfile", tool.name, tool.version, url, LOCK_FILENAME);
+        }
+        // the status is updated in place on a terminal, otherwise each step gets its own line,
+        // e.g. when several tools are downloaded at once
+        let in_place = !parallel && std::io::stderr().is_terminal();
+        if in_place {
+            eprint!("🔧 ⏳ Downloading {} {}", tool.name, tool.version);
+            std::io::stderr().flush()?;
+        } else {
+            report!("⏳ Downloading {} {}", tool.name, tool.version);
+        }
+        verbose!("Using tmp_dir {:?}", tmp_dir);
+        let extract_dir = tmp_dir.join(&tool.name);
+        if let Some(entry_dir) = self.find_in_store(tool) {
+            verbose!(
+                "Using {} {} from shared cache {:?}",
+                tool.name,
+                tool.version,
+                entry_dir
+            );
+            link_tree(&entry_dir, &extract_dir).with_context(|| {
+                format!(
+                    "Unable to copy {} {} from shared cache",
+                    tool.name, tool.version
+                )
+            })?;
+        } else {
+            self.download_and_extract(tool, url, &extract_dir, parallel)?;
+        }
+        // entries added to the shared cache by older versions have no manifest
+        if read_manifest(&extract_dir)?.is_none() {
+            let manifest = Manifest::create(&tool.name, &tool.version, url, &extract_dir)?;
+            write_manifest(&extract_dir, &manifest)?;
+        }
+        // the previous installation is only removed once the new one is in place
+        let old_dir = tmp_dir.join(format!("{}.old", tool.name));
+        if tool_dir.exists() {
+            PlatformFns::rename_atomically(&tool_dir, &old_dir).with_context(|| {
+                format!(
+                    "Unable to move {:?} out of the way to {:?}",
+                    tool_dir, old_dir
+                )
+            })?;
+        }
+        if let Err(error) = PlatformFns::rename_atomically(&extract_dir, &tool_dir) {
+            if old_dir.exists() {
+                PlatformFns::rename_atomically(&old_dir, &tool_dir).with_context(|| {
+                    format!("Unable to restore {:?} from {:?}", tool_dir, old_dir)
+                })?;
+            }
+            return Err(error.context(format!(
+                "Unable to atomically rename from {:?} to {:?}",
+                extract_dir, tool_dir
+            )));
+        }
+        if old_dir.exists() {
+            retry(|| std::fs::remove_dir_all(&old_dir))
+                .with_context(|| format!("Unable to remove {:?}", old_dir))?;
+        }
+        if in_place {
+            eprintln!("\r🔧 ✅ Downloading {} {}", tool.name, tool.version);
+        } else {
+            report!("✅ Downloaded {} {}", tool.name, tool.version);
+        }
+        Ok(())
+    }
+
+    fn download_and_extract(
+        &self,
+        tool: &ToolConfiguration,
+        url: &str,
+        extract_dir: &Path,
+        parallel: bool,
+    ) -> Result<()> {
+        let candidate_urls = self.get_candidate_urls(self.platform.get_download_urls(tool));
+        let mut errors = vec![];
+        let mut downloaded = None;
+        for candidate_url in &candidate_urls {
+            match self.download_tool(tool, url, candidate_url, parallel) {
+                Ok(download) => {
+                    downloaded = Some(download);
+                    break;
+                }
+                Err(error) => {
+                    verbose!("Download from {} failed: {:#}", candidate_url, error);
+                    errors.push((candidate_url.clone(), error));
+                }
+            }
+        }
+        let (file_path, archive_type) = match downloaded {
+            Some(download) => download,
+            None => return Err(combine_download_errors(tool, errors)),
+        };
+        let sha256 = sha256_file(&file_path)?;
+        std::fs::create_dir_all(extract_dir)
+            .with_context(|| format!("Unable to extract directory {:?}", extract_dir))?;
+        verbose!("Extracting {:?} as {:?}", file_path, archive_type);
+        archive::extract(archive_type, &file_path, tool, extract_dir)?;
+        if file_path.exists() {
+            retry(|| std::fs::remove_file(&file_path))
+                .with_context(|| format!("Unable to remove temp file at {:?}", file_path))?;
+        }
+        // the manifest is added to the shared cache as well, to verify entries before reuse
+        let manifest = Manifest::create(&tool.name, &tool.version, url, extract_dir)?;
+        write_manifest(extract_dir, &manifest)?;
+        // content without a known checksum is never taken from the shared cache, the key uses
+        // the primary url like find_in_store, regardless of the mirror used
+        if let (Some(store), Ok(format), Some(_)) = (
+            &self.shared_store,
+            get_unpack_format(tool, url),
+            self.get_known_sha256(tool, url),
+        ) {
+            let key = SharedStore::get_key(
+                &sha256,
+                &format,
+                tool.strip_directories,
+                &tool.name,
+                &tool.version,
+            );
+            // the shared cache only saves downloads, so failing to fill it is not an error
+            if let Err(error) = store.insert(&key, extract_dir) {
                 verbose!(
-                    "Tool found, skipping download for {} v{}",
+                    "Unable to add {} {} to shared cache: {:#}",
                     tool.name,
-                    tool.version
+                    tool.version,
+                    error
                 );
+            }
+        }
+        Ok(())
+    }
+
+    // Only content with a known checksum is taken from the shared cache
+    fn find_in_store(&self, tool: &ToolConfiguration) -> Option<PathBuf> {
+        let store = self.shared_store.as_ref()?;
+        let url = self.platform.get_download_urls(tool)?.first()?;
+        let sha256 = self.get_known_sha256(tool, url)?;
+        let format = get_unpack_format(tool, url).ok()?;
+        store.get(&SharedStore::get_key(
+            &sha256,
+            &format,
+            tool.strip_directories,
+            &tool.name,
+            &tool.version,
+        ))
+    }
+
+    // Checksum of the download from the configuration or the lockfile
+    fn get_known_sha256(&self, tool: &ToolConfiguration, url: &str) -> Option<String> {
+        match self.platform.get_sha256(tool) {
+            Some(sha256) => Some(sha256.to_string()),
+            None => self
+                .get_locked_artifact(tool, url)
+                .map(|artifact| artifact.sha256.clone()),
+        }
+    }
+
+    fn lock_tool(&self, tool: &ToolConfiguration) -> Result<std::fs::File> {
+        self.lock_tool_version(&tool.name, &tool.version)
+    }
+
+    // Versions are installed and removed while holding their lock
+    fn lock_tool_version(&self, name: &str, version: &str) -> Result<std::fs::File> {
+        let lock_path = self
+            .tools_dir
+            .join(".locks")
+            .join(get_lock_file_name(name, version));
+        std::fs::create_dir_all(lock_path.parent().expect("Parent should exist"))?;
+        let lock_timeout = self
+            .configuration
+            .lock_timeout
+            .map_or(DEFAULT_LOCK_TIMEOUT, std::time::Duration::from_secs);
+        lock_file(&lock_path, lock_timeout, &mut || {
+            report!(
+                "Waiting for another process to install {} {}",
+                name,
+                version
+            )
+        })
+        .with_context(|| {
+            format!(
+                "Unable to install {} {}, since another process is still installing it",
+                name, version
+            )
+        })
+    }
+
+    // Check installed tools against their manifest and reinstall damaged ones,
+    // returns the number of reinstalled tools
+    pub fn verify(&self) -> Result<usize> {
+        let mut damaged_tools = vec![];
+        for tool in &self.configuration.tools {
+            let tool_dir = self.get_tool_dir(tool);
+            if !tool_dir.exists() {
                 continue;
             }
-            std::fs::create_dir_all(&tmp_dir)
-                .with_context(|| format!("Unable to create tmp dir {:?}", tmp_dir))?;
-            std::fs::create_dir_all(tool_dir.parent().expect("Parent should exist"))?;
-            let url = self
-                .platform
-                .get_download_url(tool)
-                .with_context(|| format!("No download url configured for {}", tool.name))?;
-            let extension = url;
-            let extension = extension.splitn(2, '?').next().unwrap();
-            let extension = extension.rsplitn(2, '/').next().unwrap();
-            let extension = extension.rsplitn(2, '.').next().unwrap();
-            let file_name = format!("{}.{}", tool.name, extension);
-            print!("🔧 ⏳ Downloading {} {}", tool.name, tool.version,);
-            std::io::stdout().flush()?;
-            verbose!("Using tmp_dir {:?}", tmp_dir);
-            let file_path = tmp_dir.join(file_name);
-            download(url, &file_path).with_context(|| {
+            let problems = match read_manifest(&tool_dir) {
+                Ok(Some(manifest)) => manifest.verify(&tool_dir)?,
+                Ok(None) => {
+                    // installed by an older version, which did not write manifests
+                    report!(
+                        "Unable to verify {} {}, since it has no manifest",
+                        tool.name,
+                        tool.version
+                    );
+                    continue;
+                }
+                Err(error) => vec![format!("{:#}", error)],
+            };
+            if problems.is_empty() {
+                report!("✅ Verified {} {}", tool.name, tool.version);
+                continue;
+            }
+            let mut description = problems
+                .iter()
+                .take(MAX_REPORTED_PROBLEMS)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ");
+            if problems.len() > MAX_REPORTED_PROBLEMS {
+                description += &format!(" and {} more", problems.len() - MAX_REPORTED_PROBLEMS);
+            }
+            report!(
+                "{} {} is damaged ({}), reinstalling",
+                tool.name,
+                tool.version,
+                description
+            );
+            damaged_tools.push(tool);
+        }
+        for tool in &damaged_tools {
+            self.remove_damaged_store_entry(tool)?;
+        }
+        let reinstalled = damaged_tools.len();
+        self.install_tools(damaged_tools, true)?;
+        Ok(reinstalled)
+    }
+
+    // Download the given tools, or all tools, again and replace the installed ones
+    pub fn reinstall(&mut self, tool_names: &[String], all: bool) -> Result<()> {
+        for tool_name in tool_names {
+            if !self
+                .configuration
+                .tools
+                .iter()
+                .any(|tool| &tool.name == tool_name)
+            {
+                bail!("Tool '{}' not found", tool_name);
+            }
+        }
+        let tools: Vec<&ToolConfiguration> = self
+            .configuration
+            .tools
+            .iter()
+            .filter(|tool| all || tool_names.contains(&tool.name))
+            .collect();
+        for tool in &tools {
+            self.remove_damaged_store_entry(tool)?;
+        }
+        self.install_tools(tools, true)
+    }
+
+    // The entry in the shared cache is only removed if it is damaged, since other projects may
+    // still use it
+    fn remove_damaged_store_entry(&self, tool: &ToolConfiguration) -> Result<()> {
+        let entry_dir = match self.find_in_store(tool) {
+            Some(entry_dir) => entry_dir,
+            None => return Ok(()),
+        };
+        // entries without a manifest cannot be verified, so they are downloaded again
+        let damaged = match read_manifest(&entry_dir) {
+            Ok(Some(manifest)) => !manifest.verify(&entry_dir)?.is_empty(),
+            Ok(None) | Err(_) => true,
+        };
+        if damaged {
+            verbose!(
+                "Removing damaged {} {} from shared cache {:?}",
+                tool.name,
+                tool.version,
+                entry_dir
+            );
+            retry(|| std::fs::remove_dir_all(&entry_dir))
+                .with_context(|| format!("Unable to remove {:?}", entry_dir))?;
+        }
+        Ok(())
+    }
+
+    // Download and verify the tool from one of its urls, returns the file and its archive type
+    fn download_tool(
+        &self,
+        tool: &ToolConfiguration,
+        locked_url: &str,
+        url: &str,
+        parallel: bool,
+    ) -> Result<(PathBuf, ArchiveType)> {
+        let url_file_name = url.split('?').next().unwrap();
+        let url_file_name = url_file_name.rsplit('/').next().unwrap();
+        let archive_type = match tool.format {
+            Some(format) => Some(format),
+            None => ArchiveType::from_file_name(url_file_name)
+                .with_context(|| format!("Unable to download {} {}", tool.name, tool.version))?,
+        };
+        let extension = url_file_name.rsplit('.').next().unwrap();
+        let file_path = self.get_partial_download_path(tool, url, extension);
+        std::fs::create_dir_all(file_path.parent().expect("Parent should exist"))?;
+        if file_path.exists() {
+            verbose!("Resuming partial download {:?}", file_path);
+        }
+        let mut progress = ProgressReporter::new(
+            format!("Downloading {} {}", tool.name, tool.version),
+            !parallel,
+        );
+        let download_url = self.resolve_url(url);
+        let result = download(
+            &download_url,
+            &file_path,
+            &self.download_options(),
+            &mut |status| progress.update(status),
+        );
+        progress.finish();
+        result.with_context(|| {
+            let download_dir = file_path.parent().expect("Parent should exist");
+            format!(
+                "Unable to download {} to {:?}",
+                url,
                 format!(
-                    "Unable to download {} to {:?}",
-                    url,
-                    make_absolute(file_path.as_path()).unwrap()
+                    "{}/{}",
+                    make_absolute(download_dir).unwrap(),
+                    file_path.file_name().unwrap().to_string_lossy()
                 )
-            })?;
-            let extract_dir = tmp_dir.join(&tool.name);
-            let extension = file_path.extension();
-            std::fs::create_dir_all(&extract_dir)
-                .with_context(|| format!("Unable to extract directory {:?}", extract_dir))?;
-            let zip_extension = Some(OsStr::new("zip"));
-            let gz_extension = Some(OsStr::new("gz"));
-            let tgz_extension = Some(OsStr::new("tgz"));
-            if extension == zip_extension {
-                let file = File::open(&file_path)?;
-                let mut archive = zip::ZipArchive::new(file)
-                    .with_context(|| format!("Unable to open zip file {:?}", file_path))?;
-                for i in 0..archive.len() {
-                    let mut file = archive
-                        .by_index(i)
-                        .with_context(|| format!("Unable to open zip entry {:?}", i))?;
-                    let file_name: PathBuf =
-                        strip_filename(file.sanitized_name(), tool, file.is_dir())?;
-                    let outpath = extract_dir.join(file_name);
-
-                    if (&*file.name()).ends_with('/') {
-                        std::fs::create_dir_all(&outpath).unwrap();
-                    } else {
-                        if let Some(p) = outpath.parent() {
-                            if !p.exists() {
-                                std::fs::create_dir_all(&p)
-                                    .with_context(|| format!("Unable to zip path {:?}", p))?;
-                            }
-                        }
-                        let mut outfile = std::fs::File::create(&outpath).with_context(|| {
-                            format!("Could not create output file '{:?}'", outpath)
-                        })?;
-                        std::io::copy(&mut file, &mut outfile).with_context(|| {
-                            format!("Unable to extract file {:?} to path {:?}", outpath, outfile)
-                        })?;
-                        #[cfg(target_family = "unix")]
-                        {
-                            // Set linux file permission to make files executable
-                            if let Some(mode) = file.unix_mode() {
-                                let mut perms = std::fs::metadata(&outpath)?.permissions();
-                                perms.set_mode(mode);
-                                std::fs::set_permissions(outpath, perms)?;
-                            }
-                        }
-                    }
+            )
+        })?;
+        if let Err(error) = self.verify_download(tool, locked_url, &download_url, &file_path) {
+            retry(|| std::fs::remove_file(&file_path))
+                .with_context(|| format!("Unable to remove temp file at {:?}", file_path))?;
+            return Err(error.context(format!(
+                "Unable to verify download {} of {} {}",
+                url, tool.name, tool.version
+            )));
+        }
+        let archive_type = match archive_type {
+            Some(archive_type) => archive_type,
+            None => ArchiveType::from_content(&file_path)?,
+        };
+        Ok((file_path, archive_type))
+    }
+
+    // Urls without a scheme are local paths relative to the configuration file
+    fn resolve_url(&self, url: &str) -> String {
+        if url.contains("://") {
+            return url.to_string();
+        }
+        let config_dir = self
+            .configuration
+            .configuration_files
+            .first()
+            .and_then(|configuration_file| Path::new(configuration_file).parent())
+            .unwrap_or_else(|| Path::new(""));
+        let path = config_dir.join(url);
+        let path = match std::env::current_dir() {
+            Ok(current_dir) => current_dir.join(path),
+            Err(_) => path,
+        };
+        file_url(&path)
+    }
+
+    // Urls to try in order, each configured url is preceded by its mirrors
+    fn get_candidate_urls(&self, urls: Option<&[String]>) -> Vec<String> {
+        let mut candidates: Vec<String> = vec![];
+        for url in urls.unwrap_or_default() {
+            let mirrored_urls = self
+                .configuration
+                .mirrors
+                .iter()
+                .filter_map(|(prefix, mirror)| {
+                    url.strip_prefix(prefix.as_str())
+                        .map(|rest| format!("{}{}", mirror, rest))
+                });
+            for candidate in mirrored_urls.chain(std::iter::once(url.clone())) {
+                if !candidates.contains(&candidate) {
+                    candidates.push(candidate);
                 }
-            } else if extension == gz_extension || extension == tgz_extension {
-                let file = File::open(&file_path)?;
-                let tar = GzDecoder::new(file);
-                let mut archive = Archive::new(tar);
-                for entry in archive.entries()? {
-                    let mut entry = entry?;
-                    let path: PathBuf = strip_filename(
-                        entry.path()?.to_path_buf(),
-                        tool,
-                        entry.header().entry_type().is_dir(),
-                    )?;
-                    let outpath = extract_dir.join(path);
-                    std::fs::create_dir_all(outpath.parent().expect("parent"))?;
-                    entry
-                        .unpack(&outpath)
-                        .with_context(|| format!("Could not create output file '{:?}'", outpath))?;
+            }
+        }
+        candidates
+    }
+
+    // Download all tools for all configured platforms and record them in a lockfile
+    pub fn lock(&self) -> Result<Lockfile> {
+        if self.offline {
+            bail!("Unable to lock tools in offline mode, since this requires downloading them");
+        }
+        let (tmp_dir, tmp_dir_lock) = self.get_tmp_dir()?;
+        std::fs::create_dir_all(&tmp_dir)
+            .with_context(|| format!("Unable to create tmp dir {:?}", tmp_dir))?;
+        let result = self.lock_tools(&tmp_dir);
+        // the tmp dir is removed on failure as well, an error while locking takes precedence
+        let removed = remove_tmp_dir(&tmp_dir, tmp_dir_lock);
+        let lockfile = result?;
+        removed?;
+        Ok(lockfile)
+    }
+
+    fn lock_tools(&self, tmp_dir: &Path) -> Result<Lockfile> {
+        let mut lockfile = Lockfile::default();
+        for tool in &self.configuration.tools {
+            let mut locked_tool = LockedTool {
+                name: tool.name.clone(),
+                version: tool.version.clone(),
+                artifacts: Default::default(),
+            };
+            for (platform, urls) in tool.download.entries() {
+                let url = urls
+                    .first()
+                    .with_context(|| format!("No {} download url for {}", platform, tool.name))?;
+                let in_place = std::io::stderr().is_terminal();
+                if in_place {
+                    eprint!(
+                        "🔧 ⏳ Locking {} {} ({})",
+                        tool.name, tool.version, platform
+                    );
+                    std::io::stderr().flush()?;
+                } else {
+                    report!("⏳ Locking {} {} ({})", tool.name, tool.version, platform);
                 }
-            } else {
-                // save as tool name
-                let from = file_path.as_os_str();
-                #[cfg(target_family = "unix")]
+                let mut errors = vec![];
+                let mut resolved = None;
+                for (index, candidate_url) in
+                    self.get_candidate_urls(Some(urls)).into_iter().enumerate()
                 {
-                    let mut perms = std::fs::metadata(&file_path)?.permissions();
-                    perms.set_mode(0o755);
-                    std::fs::set_permissions(from, perms)?;
-                }
-                let extension = file_path.extension().and_then(|x| x.to_str());
-                let mut filename = tool.name.to_string();
-                if let Some(extension) = extension {
-                    if extension == "exe" {
-                        filename += ".";
-                        filename += extension;
+                    // a fresh path per candidate, so no download resumes onto another mirror's bytes
+                    let file_path = tmp_dir.join(format!("{}-{}-{}", tool.name, platform, index));
+                    let mut progress = ProgressReporter::new(
+                        format!("Locking {} {} ({})", tool.name, tool.version, platform),
+                        true,
+                    );
+                    let download_url = self.resolve_url(&candidate_url);
+                    let result = download(
+                        &download_url,
+                        &file_path,
+                        &self.download_options(),
+                        &mut |status| progress.update(status),
+                    );
+                    progress.finish();
+                    // a tampered artifact must not end up pinned in the lockfile
+                    let result = result.and_then(|resolved_url| {
+                        if let Some(sha256) = tool.sha256.get(platform) {
+                            verify_sha256(&file_path, sha256)?;
+                        }
+                        self.verify_download_signature(tool, &download_url, &file_path)?;
+                        Ok(resolved_url)
+                    });
+                    match result {
+                        Ok(resolved_url) => {
+                            // local paths differ between machines, so they are recorded as configured
+                            let resolved_url = if resolved_url.starts_with("file://") {
+                                candidate_url
+                            } else {
+                                resolved_url
+                            };
+                            resolved = Some((resolved_url, file_path));
+                            break;
+                        }
+                        Err(error) => {
+                            verbose!("Download from {} failed: {:#}", candidate_url, error);
+                            let error = error.context(format!(
+                                "Unable to download {} to {:?}",
+                                candidate_url, file_path
+                            ));
+                            errors.push((candidate_url, error));
+                        }
                     }
                 }
-                let to = extract_dir.join(filename);
-                retry(|| std::fs::rename(from, &to))
-                    .with_context(|| format!("Unable to rename from {:?} to {:?}", from, to))?;
+                let (resolved_url, file_path) = match resolved {
+                    Some(resolved) => resolved,
+                    None => return Err(combine_download_errors(tool, errors)),
+                };
+                locked_tool.artifacts.insert(
+                    platform.to_string(),
+                    LockedArtifact {
+                        url: url.to_string(),
+                        resolved_url,
+                        size: std::fs::metadata(&file_path)?.len(),
+                        sha256: sha256_file(&file_path)?,
+                    },
+                );
+                retry(|| std::fs::remove_file(&file_path))
+                    .with_context(|| format!("Unable to remove temp file at {:?}", file_path))?;
+                if in_place {
+                    eprintln!(
+                        "\r🔧 ✅ Locking {} {} ({})",
+                        tool.name, tool.version, platform
+                    );
+                } else {
+                    report!("✅ Locked {} {} ({})", tool.name, tool.version, platform);
+                }
+            }
+            lockfile.tools.push(locked_tool);
+        }
+        Ok(lockfile)
+    }
+
+    // The tmp dir is locked until the returned lock is dropped
+    fn get_tmp_dir(&self) -> Result<(PathBuf, std::fs::File)> {
+        let tmp_dir = self.tools_dir.join(format!(
+            ".tmp/{}-{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_nanos(),
+            std::process::id()
+        ));
+        let lock = lock_tmp_dir(&tmp_dir)?;
+        Ok((tmp_dir, lock))
+    }
+
+    fn download_options(&self) -> DownloadOptions {
+        let mut options = DownloadOptions {
+            proxy: ProxySettings::from_env(self.configuration.proxy.as_deref()),
+            credentials: Credentials::new(self.configuration.credentials.clone()),
+            ..DownloadOptions::default()
+        };
+        if let Some(retries) = self.configuration.download_retries {
+            options.retries = retries;
+        }
+        options
+    }
+
+    // Downloads are kept across runs, so that interrupted downloads can be resumed
+    fn get_partial_download_path(
+        &self,
+        tool: &ToolConfiguration,
+        url: &str,
+        extension: &str,
+    ) -> PathBuf {
+        let url_hash = sha256_hex(url.as_bytes());
+        self.tools_dir.join(".tmp").join("partial").join(format!(
+            "{}-{}-{}.{}",
+            tool.name,
+            tool.version,
+            &url_hash[..PARTIAL_URL_HASH_LENGTH],
+            extension
+        ))
+    }
+
+    fn get_locked_artifact(&self, tool: &ToolConfiguration, url: &str) -> Option<&LockedArtifact> {
+        self.lockfile
+            .as_ref()
+            .and_then(|lockfile| lockfile.get_artifact(&tool.name, &tool.version, url))
+    }
+
+    fn verify_download(
+        &self,
+        tool: &ToolConfiguration,
+        locked_url: &str,
+        url: &str,
+        file_path: &Path,
+    ) -> Result<()> {
+        if let Some(sha256) = self.platform.get_sha256(tool) {
+            verify_sha256(file_path, sha256)?;
+        }
+        if let Some(artifact) = self.get_locked_artifact(tool, locked_url) {
+            let size = std::fs::metadata(file_path)?.len();
+            if size != artifact.size {
+                bail!(
+                    "Download size {} does not match size {} recorded in {}",
+                    size,
+                    artifact.size,
+                    LOCK_FILENAME
+                );
             }
-            PlatformFns::rename_atomically(&extract_dir, &tool_dir).with_context(|| {
+            verify_sha256(file_path, &artifact.sha256).with_context(|| {
                 format!(
-                    "Unable to atomically rename from {:?} to {:?}",
-                    extract_dir, tool_dir
+                    "Download does not match checksum recorded in {}",
+                    LOCK_FILENAME
                 )
             })?;
-            println!("\r🔧 ✅ Downloading {} {}", tool.name, tool.version,);
         }
+        self.verify_download_signature(tool, url, file_path)
+    }
+
+    fn verify_download_signature(
+        &self,
+        tool: &ToolConfiguration,
+        url: &str,
+        file_path: &Path,
+    ) -> Result<()> {
+        if let Some(signature) = &tool.signature {
+            let signature_url = template(&signature.url, |name| match name {
+                "url" => Ok(url.to_string()),
+                name => bail!("Unsupported template: '{}'", name),
+            })?;
+            let mut signature_path = file_path.as_os_str().to_owned();
+            signature_path.push(".minisig");
+            let signature_path = PathBuf::from(signature_path);
+            download(
+                &signature_url,
+                &signature_path,
+                &self.download_options(),
+                &mut |_| {},
+            )
+            .with_context(|| format!("Unable to download signature {}", signature_url))?;
+            let signature_text = std::fs::read_to_string(&signature_path);
+            retry(|| std::fs::remove_file(&signature_path))
+                .with_context(|| format!("Unable to remove temp file at {:?}", signature_path))?;
+            let signature_text = signature_text
+                .with_context(|| format!("Unable to read signature {}", signature_url))?;
+            verify_signature(file_path, &signature_text, &signature.public_key)
+                .with_context(|| format!("Invalid signature {}", signature_url))?;
+        }
+        Ok(())
+    }
 
-        if tmp_dir.exists() {
-            retry(|| std::fs::remove_dir_all(&tmp_dir))
-                .with_context(|| format!("Could not remove temp dir {:?}", tmp_dir))?;
+    // Remove tool versions which are no longer configured and leftovers of crashed runs,
+    // returns the number of bytes reclaimed
+    pub fn clean(&self, all: bool, shared: bool) -> Result<u64> {
+        let mut reclaimed = 0;
+        // the shared cache is used by other projects as well, so it is only removed on request
+        if shared {
+            if let Some(store) = &self.shared_store {
+                if store.root().exists() {
+                    reclaimed += remove_dir(store.root())?;
+                    report!("Removed shared cache {:?}", store.root());
+                }
+            }
+        }
+        if all {
+            if self.tools_dir.exists() {
+                reclaimed += remove_dir(&self.tools_dir)?;
+                report!("Removed all tools in {:?}", self.tools_dir);
+            }
+            return Ok(reclaimed);
+        }
+        if !self.tools_dir.exists() {
+            return Ok(reclaimed + self.clean_tmp_dir()?);
+        }
+        let is_configured = |name: &str, version: &str| {
+            self.configuration
+                .tools
+                .iter()
+                .any(|tool| tool.name == name && tool.version == version)
+        };
+        for tool_entry in std::fs::read_dir(&self.tools_dir)? {
+            let tool_entry = tool_entry?;
+            let name = tool_entry.file_name().to_string_lossy().to_string();
+            // skip tmp dirs and lock files
+            if name.starts_with('.') || !tool_entry.file_type()?.is_dir() {
+                continue;
+            }
+            for version_entry in std::fs::read_dir(tool_entry.path())? {
+                let version_entry = version_entry?;
+                let version = version_entry.file_name().to_string_lossy().to_string();
+                // only version dirs are created here, other files are left alone
+                if !version_entry.file_type()?.is_dir() {
+                    continue;
+                }
+                if !is_configured(&name, &version) {
+                    // another process may still be installing this version
+                    let _lock = self.lock_tool_version(&name, &version)?;
+                    let size = remove_dir(&version_entry.path())?;
+                    report!(
+                        "Removed {} {} ({})",
+                        name,
+                        version,
+                        format_bytes(size as f64)
+                    );
+                    reclaimed += size;
+                }
+            }
+            if std::fs::read_dir(tool_entry.path())?.next().is_none() {
+                retry(|| std::fs::remove_dir(tool_entry.path()))
+                    .with_context(|| format!("Unable to remove {:?}", tool_entry.path()))?;
+            }
         }
+        self.clean_tool_locks()?;
+        reclaimed += self.clean_tmp_dir()?;
+        Ok(reclaimed)
+    }
 
+    // Remove the lock files of tools which are neither configured nor installed
+    fn clean_tool_locks(&self) -> Result<()> {
+        let locks_dir = self.tools_dir.join(".locks");
+        if !locks_dir.exists() {
+            return Ok(());
+        }
+        let mut used_locks: HashSet<String> = self
+            .configuration
+            .tools
+            .iter()
+            .map(|tool| get_lock_file_name(&tool.name, &tool.version))
+            .collect();
+        for tool_entry in std::fs::read_dir(&self.tools_dir)? {
+            let tool_entry = tool_entry?;
+            let name = tool_entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || !tool_entry.file_type()?.is_dir() {
+                continue;
+            }
+            for version_entry in std::fs::read_dir(tool_entry.path())? {
+                let version = version_entry?.file_name().to_string_lossy().to_string();
+                used_locks.insert(get_lock_file_name(&name, &version));
+            }
+        }
+        for entry in std::fs::read_dir(&locks_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if used_locks.contains(&name) {
+                continue;
+            }
+            // the lock is held while another process is installing the tool
+            if let Some(_lock) = try_lock_file(&entry.path())? {
+                retry(|| std::fs::remove_file(entry.path()))
+                    .with_context(|| format!("Unable to remove {:?}", entry.path()))?;
+                verbose!("Removed lock file {:?}", entry.path());
+            }
+        }
         Ok(())
     }
 
+    fn clean_tmp_dir(&self) -> Result<u64> {
+        let tmp_dir = self.tools_dir.join(".tmp");
+        let mut tmp_dirs = find_tmp_dirs(&tmp_dir, &|name| name != "partial")?;
+        if let Some(store) = &self.shared_store {
+            tmp_dirs.extend(store.get_tmp_dirs()?);
+        }
+        let mut reclaimed = 0;
+        for dir in tmp_dirs {
+            // the lock is held by the process using the tmp dir
+            if let Some(lock) = try_lock_file(&get_tmp_dir_lock_path(&dir))? {
+                if dir.exists() {
+                    reclaimed += get_dir_size(&dir)?;
+                }
+                remove_tmp_dir(&dir, lock)?;
+                verbose!("Removed orphaned tmp dir {:?}", dir);
+            }
+        }
+        // partial downloads are only kept for tools which are still configured
+        let partial_dir = tmp_dir.join("partial");
+        if partial_dir.exists() {
+            for entry in std::fs::read_dir(&partial_dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                let configured = self
+                    .configuration
+                    .tools
+                    .iter()
+                    .any(|tool| is_partial_download_of(&name, tool));
+                if !configured {
+                    let size = entry.metadata()?.len();
+                    retry(|| std::fs::remove_file(entry.path()))
+                        .with_context(|| format!("Unable to remove {:?}", entry.path()))?;
+                    verbose!("Removed partial download {:?}", entry.path());
+                    reclaimed += size;
+                }
+            }
+        }
+        if reclaimed > 0 {
+            report!(
+                "Removed temporary files ({})",
+                format_bytes(reclaimed as f64)
+            );
+        }
+        Ok(reclaimed)
+    }
+
     pub fn get_tool_dir(&self, tool: &ToolConfiguration) -> PathBuf {
         self.tools_dir.join(&tool.name).join(&tool.version)
     }
 
     pub fn get_command_line(&self, command: &str) -> Result<CommandLine> {
+        self.resolve_command_line(command, &[])
+    }
+
+    // The chain holds the commands currently being resolved through ${cmd:...} templates
+    fn resolve_command_line(&self, command: &str, chain: &[&str]) -> Result<CommandLine> {
+        if chain.contains(&command) {
+            let start = chain
+                .iter()
+                .position(|name| *name == command)
+                .expect("Command should be in chain");
+            bail!(
+                "Cyclic command reference: {} -> {}",
+                chain[start..].join(" -> "),
+                command
+            );
+        }
+        let chain: Vec<&str> = chain.iter().copied().chain(Some(command)).collect();
         let tool_configuration = self
             .configuration
             .tools
@@ -221,46 +1158,42 @@ impl Cache {
             .map(Deref::deref)
             .unwrap_or(command);
         let tool_dir = self.get_tool_dir(tool_configuration);
-        let replace_fn = |name: &str| match name {
-            "dir" => make_absolute(tool_dir.as_path()),
-            "version" => Ok(tool_configuration.version.to_string()),
-            name => {
-                if let Some(command_name) = name.strip_prefix("cmd:") {
-                    let command_line = self.get_command_line(command_name).with_context(|| {
-                        format!("Could not find tool command '{}'", command_name)
-                    })?;
-                    Ok(command_line.binary)
-                } else if let Some(var) = name.strip_prefix("env:") {
-                    Ok(std::env::var(var).with_context(|| {
-                        format!("Could not retrieve environment variable '{}'", var)
-                    })?)
-                } else if let Some(tool_name) = name.strip_prefix("dir:") {
-                    let tool = self
-                        .configuration
-                        .tools
-                        .iter()
-                        .find(|tool| tool.name == tool_name)
-                        .with_context(|| {
-                            format!("Could not find tool '{}' in tools list", tool_name)
-                        })?;
-                    let tool_dir = self.get_tool_dir(tool);
-                    make_absolute(tool_dir.as_path())
-                } else if let Some(rest) = name.strip_prefix("linux:") {
-                    if self.platform.get_name() == "linux" {
-                        Ok(rest.to_string())
-                    } else {
-                        Ok("".to_string())
-                    }
-                } else if let Some(rest) = name.strip_prefix("windows:") {
-                    if self.platform.get_name() == "windows" {
-                        Ok(rest.to_string())
-                    } else {
-                        Ok("".to_string())
+        let replace_fn = |name: &str| match CommandTemplate::parse(name) {
+            Some(CommandTemplate::Dir) => make_absolute(tool_dir.as_path()),
+            Some(CommandTemplate::Version) => Ok(tool_configuration.version.to_string()),
+            Some(CommandTemplate::Command(command_name)) => {
+                // errors like cyclic references already describe the problem
+                let command_line = match self.resolve_command_line(command_name, &chain) {
+                    Err(error) if error.is::<CommandNotFoundError>() => {
+                        return Err(error
+                            .context(format!("Could not find tool command '{}'", command_name)))
                     }
+                    result => result?,
+                };
+                Ok(command_line.binary)
+            }
+            Some(CommandTemplate::Env(var)) => Ok(std::env::var(var)
+                .with_context(|| format!("Could not retrieve environment variable '{}'", var))?),
+            Some(CommandTemplate::ToolDir(tool_name)) => {
+                let tool = self
+                    .configuration
+                    .tools
+                    .iter()
+                    .find(|tool| tool.name == tool_name)
+                    .with_context(|| {
+                        format!("Could not find tool '{}' in tools list", tool_name)
+                    })?;
+                let tool_dir = self.get_tool_dir(tool);
+                make_absolute(tool_dir.as_path())
+            }
+            Some(CommandTemplate::Platform(platform, text)) => {
+                if self.platform.get_name() == platform {
+                    Ok(text.to_string())
                 } else {
-                    bail!("Unsupported template: '{}'", name)
+                    Ok("".to_string())
                 }
             }
+            None => bail!("Unsupported template: '{}'", name),
         };
         let command_results: Vec<Result<String>> = command_line
             .split(' ')
@@ -296,24 +1229,125 @@ impl Cache {
     }
 }
 
-fn strip_filename(file_name: PathBuf, tool: &ToolConfiguration, is_dir: bool) -> Result<PathBuf> {
-    let new_file_name: PathBuf = file_name
-        .components()
-        .skip(tool.strip_directories)
-        .collect();
-    if !is_dir && new_file_name.components().next().is_none() {
-        bail!("File name {:?} was empty after stripping {} path components (in {:?}).\nHINT: Try setting strip_components: 0 in the tool configuration for {}", file_name, tool.strip_directories, tool.name, tool.name);
+// Group the tools so that every tool comes after the tools it requires
+fn get_install_stages<'a>(tools: &[&'a ToolConfiguration]) -> Vec<Vec<&'a ToolConfiguration>> {
+    // depths are memoized, since tools may be required by many others
+    fn get_depth<'a>(
+        tools: &[&'a ToolConfiguration],
+        tool: &'a ToolConfiguration,
+        depths: &mut HashMap<&'a str, usize>,
+    ) -> usize {
+        if let Some(depth) = depths.get(tool.name.as_str()) {
+            return *depth;
+        }
+        let depth = tool
+            .requires
+            .iter()
+            .filter_map(|name| tools.iter().find(|tool| &tool.name == name))
+            .map(|required| get_depth(tools, required, depths) + 1)
+            .max()
+            .unwrap_or(0);
+        depths.insert(&tool.name, depth);
+        depth
+    }
+    let mut depths = HashMap::new();
+    let mut stages: Vec<Vec<&ToolConfiguration>> = vec![];
+    for tool in tools {
+        let depth = get_depth(tools, tool, &mut depths);
+        if stages.len() <= depth {
+            stages.resize(depth + 1, vec![]);
+        }
+        stages[depth].push(tool);
     }
-    Ok(new_file_name)
+    stages
 }
 
-#[cfg(test)]
-mod tests {
-    use mockito::mock;
-    use std::io::{Cursor, Write};
+// Partial downloads are named <name>-<version>-<url hash>.<extension>, matching the whole name
+// keeps version 1 from claiming the partial downloads of version 1-rc
+fn is_partial_download_of(file_name: &str, tool: &ToolConfiguration) -> bool {
+    let rest = match file_name.strip_prefix(&format!("{}-{}-", tool.name, tool.version)) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    match rest.split_once('.') {
+        Some((url_hash, _)) => {
+            url_hash.len() == PARTIAL_URL_HASH_LENGTH
+                && url_hash.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+fn get_lock_file_name(name: &str, version: &str) -> String {
+    format!("{}-{}.lock", name, version)
+}
+
+// Remove the directory, returns the size of the files in it
+fn remove_dir(path: &Path) -> Result<u64> {
+    let size = get_dir_size(path)?;
+    retry(|| std::fs::remove_dir_all(path))
+        .with_context(|| format!("Unable to remove {:?}", path))?;
+    Ok(size)
+}
+
+fn get_dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += get_dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+// How the download is unpacked, as far as this can be told from the url
+fn get_unpack_format(tool: &ToolConfiguration, url: &str) -> Result<String> {
+    let url_file_name = url.split('?').next().unwrap();
+    let url_file_name = url_file_name.rsplit('/').next().unwrap();
+    let archive_type = match tool.format {
+        Some(format) => Some(format),
+        None => ArchiveType::from_file_name(url_file_name)?,
+    };
+    let extension = url_file_name.rsplit('.').next().unwrap();
+    Ok(format!("{:?} {}", archive_type, extension))
+}
+
+// Report every failed url if none of the urls of a tool could be downloaded
+fn combine_download_errors(
+    tool: &ToolConfiguration,
+    mut errors: Vec<(String, anyhow::Error)>,
+) -> anyhow::Error {
+    if errors.len() <= 1 {
+        return match errors.pop() {
+            Some((_, error)) => error,
+            None => anyhow::anyhow!("No download url configured for {}", tool.name),
+        };
+    }
+    let failures: Vec<String> = errors
+        .iter()
+        .map(|(url, error)| format!("  {}: {:#}", url, error))
+        .collect();
+    anyhow::anyhow!(
+        "Unable to download {} {} from any of {} urls:\n{}",
+        tool.name,
+        tool.version,
+        errors.len(),
+        failures.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::mock;
+    use std::io::{Cursor, Write};
 
     use super::*;
-    use crate::config::DownloadUrls;
+    use crate::config::{DownloadUrls, SignatureConfiguration};
+    use crate::manifest::MANIFEST_FILENAME;
     use std::fs::read_to_string;
     use tempfile::TempDir;
 
@@ -329,6 +1363,8 @@ mod tests {
         let mut configuration: Configuration = Default::default();
         let temp_dir = tempfile::tempdir().unwrap();
         configuration.cache_dir = Some(temp_dir.path().to_str().unwrap().to_string());
+        configuration.shared_cache_dir =
+            Some(temp_dir.path().join("shared").to_str().unwrap().to_string());
         (configuration, temp_dir)
     }
 
@@ -352,13 +1388,14 @@ mod tests {
             name: "foo".to_string(),
             version: "1.2.3".to_string(),
             download: DownloadUrls {
-                default: Some(mockito::server_url() + path),
+                default: Some(vec![mockito::server_url() + path]),
                 linux: None,
                 windows: None,
             },
             commands,
             env: env.clone(),
             strip_directories: 0,
+            ..Default::default()
         });
         let mut cache = Cache::create(configuration).unwrap();
         cache.init().unwrap();
@@ -400,6 +1437,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cyclic_command_references() {
+        let (mut configuration, _temp_dir) = create_configuration();
+        let mut commands = HashMap::new();
+        commands.insert("self".to_string(), "${cmd:self}".to_string());
+        commands.insert("ping".to_string(), "${cmd:pong}".to_string());
+        commands.insert("pong".to_string(), "pong ${cmd:ping}".to_string());
+        commands.insert("serve".to_string(), "${cmd:ping}".to_string());
+        commands.insert("broken".to_string(), "${cmd:missing}".to_string());
+        configuration.tools.push(ToolConfiguration {
+            name: "foo".to_string(),
+            version: "1.2.3".to_string(),
+            commands,
+            ..Default::default()
+        });
+        let cache = Cache::create(configuration).unwrap();
+        let get_error = |command: &str| {
+            let error = cache.get_command_line(command).expect_err("error expected");
+            // only missing commands are reported as such
+            let not_found = error
+                .chain()
+                .any(|cause| cause.to_string().starts_with("Could not find tool command"));
+            (error.root_cause().to_string(), not_found)
+        };
+        assert_eq!(
+            get_error("self"),
+            ("Cyclic command reference: self -> self".to_string(), false)
+        );
+        assert_eq!(
+            get_error("ping"),
+            (
+                "Cyclic command reference: ping -> pong -> ping".to_string(),
+                false
+            )
+        );
+        assert_eq!(
+            get_error("serve"),
+            (
+                "Cyclic command reference: ping -> pong -> ping".to_string(),
+                false
+            )
+        );
+        assert_eq!(
+            get_error("broken"),
+            ("Command 'missing' not found".to_string(), true)
+        );
+    }
+
     #[test]
     fn download_with_query_params_in_url() {
         let path = "/cache/toolq";
@@ -412,13 +1497,14 @@ mod tests {
             name: "foo".to_string(),
             version: "1.2.3".to_string(),
             download: DownloadUrls {
-                default: Some(mockito::server_url() + path + "?foo=bar/baz?xyz"),
+                default: Some(vec![mockito::server_url() + path + "?foo=bar/baz?xyz"]),
                 linux: None,
                 windows: None,
             },
             commands: Default::default(),
             env: Default::default(),
             strip_directories: 0,
+            ..Default::default()
         });
         let mut cache = Cache::create(configuration).unwrap();
         cache.init().unwrap();
@@ -432,6 +1518,122 @@ mod tests {
         assert_eq!(content, "world");
     }
 
+    fn create_mirrored_configuration(urls: Vec<String>) -> (Configuration, tempfile::TempDir) {
+        let (mut configuration, temp_dir) = create_configuration();
+        configuration.tools.push(ToolConfiguration {
+            name: "foo".to_string(),
+            version: "1.2.3".to_string(),
+            download: DownloadUrls {
+                default: Some(urls),
+                linux: None,
+                windows: None,
+            },
+            ..Default::default()
+        });
+        (configuration, temp_dir)
+    }
+
+    #[test]
+    fn download_from_fallback_url() {
+        let _m = mock("GET", "/cache/fallback-primary")
+            .with_status(404)
+            .create();
+        let _m2 = mock("GET", "/cache/fallback-secondary")
+            .with_status(200)
+            .with_body("fallback")
+            .create();
+        let (configuration, temp_dir) = create_mirrored_configuration(vec![
+            mockito::server_url() + "/cache/fallback-primary",
+            mockito::server_url() + "/cache/fallback-secondary",
+        ]);
+        let mut cache = Cache::create(configuration).unwrap();
+        cache.init().unwrap();
+        let path = temp_dir.path().join("tools/foo/1.2.3/foo");
+        assert_eq!(read_to_string(path).unwrap(), "fallback");
+    }
+
+    #[test]
+    fn download_from_mirror() {
+        let _m = mock("GET", "/mirror/releases/tool")
+            .with_status(200)
+            .with_body("mirrored")
+            .create();
+        let (mut configuration, temp_dir) =
+            create_mirrored_configuration(vec!["https://github.invalid/releases/tool".to_string()]);
+        configuration.mirrors.insert(
+            "https://github.invalid/".to_string(),
+            mockito::server_url() + "/mirror/",
+        );
+        let mut cache = Cache::create(configuration).unwrap();
+        assert_eq!(
+            cache.get_candidate_urls(
+                cache
+                    .platform
+                    .get_download_urls(&cache.configuration.tools[0])
+            ),
+            vec![
+                mockito::server_url() + "/mirror/releases/tool",
+                "https://github.invalid/releases/tool".to_string()
+            ]
+        );
+        cache.init().unwrap();
+        let path = temp_dir.path().join("tools/foo/1.2.3/foo");
+        assert_eq!(read_to_string(path).unwrap(), "mirrored");
+    }
+
+    #[test]
+    fn download_from_relative_path() {
+        let (mut configuration, temp_dir) =
+            create_mirrored_configuration(vec!["vendor/foo.tar.gz".to_string()]);
+        let config_dir = tempfile::tempdir().unwrap();
+        configuration.configuration_files = vec![config_dir
+            .path()
+            .join(".tool-tool.v1.yaml")
+            .to_string_lossy()
+            .to_string()];
+        std::fs::create_dir_all(config_dir.path().join("vendor")).unwrap();
+        std::fs::write(
+            config_dir.path().join("vendor/foo.tar.gz"),
+            gzip(&create_hello_world_tar()),
+        )
+        .unwrap();
+        let mut cache = Cache::create(configuration).unwrap();
+        cache.init().unwrap();
+        let path = temp_dir.path().join("tools/foo/1.2.3/foo/hello_world.txt");
+        assert_eq!(read_to_string(path).unwrap(), "Hello, World!");
+        // the lockfile is shared, so it must not contain the local path
+        let lockfile = cache.lock().unwrap();
+        assert_eq!(
+            lockfile.tools[0].artifacts["default"].resolved_url,
+            "vendor/foo.tar.gz"
+        );
+    }
+
+    #[test]
+    fn download_fails_for_all_urls() {
+        let _m = mock("GET", "/cache/all-fail1").with_status(404).create();
+        let _m2 = mock("GET", "/cache/all-fail2").with_status(403).create();
+        let (configuration, _temp_dir) = create_mirrored_configuration(vec![
+            mockito::server_url() + "/cache/all-fail1",
+            mockito::server_url() + "/cache/all-fail2",
+        ]);
+        let mut cache = Cache::create(configuration).unwrap();
+        let error = format!("{:#}", cache.init().unwrap_err());
+        assert!(
+            error.starts_with("Unable to download foo 1.2.3 from any of 2 urls:\n"),
+            "{}",
+            error
+        );
+        assert!(error.contains(&format!(
+            "HTTP Error 404 downloading {}/cache/all-fail1",
+            mockito::server_url()
+        )));
+        assert!(error.contains(&format!(
+            "HTTP Error 403 downloading {}/cache/all-fail2",
+            mockito::server_url()
+        )));
+    }
+
     #[test]
     fn do_not_download_existing_tool() {
         let (mut configuration, temp_dir) = create_configuration();
@@ -439,13 +1641,14 @@ mod tests {
             name: "foo".to_string(),
             version: "1.2.3".to_string(),
             download: DownloadUrls {
-                default: Some("http://url.invalid/tool".to_string()),
+                default: Some(vec!["http://url.invalid/tool".to_string()]),
                 linux: None,
                 windows: None,
             },
             commands: HashMap::new(),
             env: HashMap::new(),
             strip_directories: 0,
+            ..Default::default()
         });
         std::fs::create_dir_all(temp_dir.path().join("tools").join("foo").join("1.2.3")).unwrap();
         let mut cache = Cache::create(configuration).unwrap();
@@ -490,13 +1693,14 @@ mod tests {
             name: "foo".to_string(),
             version: "1.2.3".to_string(),
             download: DownloadUrls {
-                default: Some(mockito::server_url() + path),
+                default: Some(vec![mockito::server_url() + path]),
                 linux: None,
                 windows: None,
             },
             commands: HashMap::new(),
             env: HashMap::new(),
             strip_directories: 1,
+            ..Default::default()
         });
         let mut cache = Cache::create(configuration).unwrap();
         cache.platform = Box::new(crate::platform::Windows {});
@@ -511,6 +1715,682 @@ mod tests {
         assert_eq!(content, "Hello, World!");
     }
 
+    #[test]
+    fn download_with_matching_sha256() {
+        let path = "/cache/tool_sha256_ok";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 1);
+        cache.configuration.tools[0].sha256.default =
+            Some("DFFD6021BB2BD5B0AF676290809EC3A53191DD81C7F70A4B28688A362182986F".to_string());
+        cache.init().unwrap();
+        let path = temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .join("foo");
+        let content = read_to_string(path).expect("File foo should exist");
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[test]
+    fn download_with_mismatching_sha256() {
+        let path = "/cache/tool_sha256_mismatch";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body(b"Hello, World?")
+            .create();
+        let (temp_dir, mut cache) = create_cache(path);
+        cache.configuration.tools[0].sha256.default =
+            Some("dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f".to_string());
+        let error = cache.init().expect_err("checksum error expected");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Unable to verify download {} of foo 1.2.3",
+                mockito::server_url() + path
+            )
+        );
+        assert_eq!(
+            error.root_cause().to_string(),
+            "SHA-256 checksum mismatch: expected dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f, actual f16c3bb0532537acd5b2e418f2b1235b29181e35cffee7cc29d84de4a1d62e4d"
+        );
+        assert!(!temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .exists());
+    }
+
+    const HELLO_WORLD_SHA256: &str =
+        "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f";
+
+    fn create_lockfile(url: &str, size: u64, sha256: &str) -> Lockfile {
+        let mut locked_tool = LockedTool {
+            name: "foo".to_string(),
+            version: "1.2.3".to_string(),
+            artifacts: Default::default(),
+        };
+        locked_tool.artifacts.insert(
+            "default".to_string(),
+            LockedArtifact {
+                url: url.to_string(),
+                resolved_url: url.to_string(),
+                size,
+                sha256: sha256.to_string(),
+            },
+        );
+        Lockfile {
+            tools: vec![locked_tool],
+        }
+    }
+
+    #[test]
+    fn lock_tools() {
+        let path = "/cache/lock_default";
+        let linux_path = "/cache/lock_linux";
+        let redirect_path = "/cache/lock_linux_redirect";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body(b"Hello, World!")
+            .create();
+        let _m2 = mock("GET", redirect_path)
+            .with_status(302)
+            .with_header("location", &(mockito::server_url() + linux_path))
+            .create();
+        let _m3 = mock("GET", linux_path)
+            .with_status(200)
+            .with_body(b"Hello, World?")
+            .create();
+        let (_temp_dir, mut cache) = create_cache(path);
+        cache.configuration.tools[0].download.linux =
+            Some(vec![mockito::server_url() + redirect_path]);
+        let lockfile = cache.lock().unwrap();
+        assert_eq!(lockfile.tools.len(), 1);
+        let locked_tool = &lockfile.tools[0];
+        assert_eq!(locked_tool.name, "foo");
+        assert_eq!(locked_tool.version, "1.2.3");
+        assert_eq!(
+            locked_tool.artifacts.get("default"),
+            Some(&LockedArtifact {
+                url: mockito::server_url() + path,
+                resolved_url: mockito::server_url() + path,
+                size: 13,
+                sha256: HELLO_WORLD_SHA256.to_string(),
+            })
+        );
+        assert_eq!(
+            locked_tool.artifacts.get("linux"),
+            Some(&LockedArtifact {
+                url: mockito::server_url() + redirect_path,
+                resolved_url: mockito::server_url() + linux_path,
+                size: 13,
+                sha256: "f16c3bb0532537acd5b2e418f2b1235b29181e35cffee7cc29d84de4a1d62e4d"
+                    .to_string(),
+            })
+        );
+        assert_eq!(locked_tool.artifacts.get("windows"), None);
+    }
+
+    #[test]
+    fn lock_only_verified_artifacts() {
+        let path = "/cache/lock_tampered";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body(b"Hello, Moon!")
+            .create();
+        let (_temp_dir, mut cache) = create_cache(path);
+        cache.configuration.tools[0].sha256.default = Some(HELLO_WORLD_SHA256.to_string());
+        let error = format!("{:#}", cache.lock().unwrap_err());
+        assert!(
+            error.contains("SHA-256 checksum mismatch: expected dffd6021"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn lock_removes_tmp_dir_on_failure() {
+        let path = "/cache/lock_failure";
+        let _m = mock("GET", path).with_status(404).create();
+        let (temp_dir, cache) = create_cache(path);
+        cache.lock().expect_err("download failure expected");
+        let tmp_dirs = std::fs::read_dir(temp_dir.path().join("tools/.tmp"))
+            .unwrap()
+            .count();
+        assert_eq!(tmp_dirs, 0);
+    }
+
+    #[test]
+    fn download_matching_lockfile() {
+        let path = "/cache/tool_locked";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 1);
+        cache.lockfile = Some(create_lockfile(
+            &(mockito::server_url() + path),
+            13,
+            HELLO_WORLD_SHA256,
+        ));
+        cache.frozen = true;
+        cache.init().unwrap();
+        assert!(temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .join("foo")
+            .exists());
+    }
+
+    #[test]
+    fn download_not_matching_lockfile() {
+        let path = "/cache/tool_locked_mismatch";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body(b"Hello, World?")
+            .create();
+        let (temp_dir, mut cache) = create_cache(path);
+        cache.lockfile = Some(create_lockfile(
+            &(mockito::server_url() + path),
+            13,
+            HELLO_WORLD_SHA256,
+        ));
+        let error = cache.init().expect_err("lockfile mismatch expected");
+        assert_eq!(
+            format!("{:#}", error),
+            format!("Unable to verify download {} of foo 1.2.3: Download does not match checksum recorded in .tool-tool.v1.lock: SHA-256 checksum mismatch: expected {}, actual f16c3bb0532537acd5b2e418f2b1235b29181e35cffee7cc29d84de4a1d62e4d", mockito::server_url() + path, HELLO_WORLD_SHA256)
+        );
+        assert!(!temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .exists());
+    }
+
+    #[test]
+    fn download_not_matching_lockfile_size() {
+        let path = "/cache/tool_locked_size_mismatch";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body(b"Hello, World!!")
+            .create();
+        let (_temp_dir, mut cache) = create_cache(path);
+        cache.lockfile = Some(create_lockfile(
+            &(mockito::server_url() + path),
+            13,
+            HELLO_WORLD_SHA256,
+        ));
+        let error = cache.init().expect_err("lockfile mismatch expected");
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Download size 14 does not match size 13 recorded in .tool-tool.v1.lock"
+        );
+    }
+
+    #[test]
+    fn frozen_refuses_unlocked_download() {
+        let path = "/cache/tool_unlocked";
+        let (_m, _temp_dir, mut cache) = create_mocked_cache(path, 0);
+        cache.lockfile = Some(create_lockfile(
+            "http://example.com/other",
+            13,
+            HELLO_WORLD_SHA256,
+        ));
+        cache.frozen = true;
+        let error = cache.init().expect_err("frozen error expected");
+        assert_eq!(
+            error.to_string(),
+            format!("Refusing to download foo 1.2.3 from {} in frozen mode, since it is not recorded in .tool-tool.v1.lock\nHINT: Run 'tt --lock' to update the lockfile", mockito::server_url() + path)
+        );
+        _m.assert();
+    }
+
+    #[test]
+    fn offline_refuses_download() {
+        let path = "/cache/tool_offline";
+        let (_m, _temp_dir, mut cache) = create_mocked_cache(path, 0);
+        cache.offline = true;
+        let error = cache.init().expect_err("offline error expected");
+        assert_eq!(
+            error.to_string(),
+            "Unable to install missing tools in offline mode:\n  foo 1.2.3\nHINT: Run 'tt --download' with network access to install them"
+        );
+        std::fs::create_dir_all(cache.get_tool_dir(&cache.configuration.tools[0])).unwrap();
+        cache.init().unwrap();
+        _m.assert();
+    }
+
+    #[test]
+    fn install_only_tools_needed_by_command() {
+        let (mut configuration, temp_dir) = create_configuration();
+        let mut mocks = vec![];
+        for (name, command, expected_downloads) in &[
+            ("lazy_run", "${cmd:lazy_helper} ${dir:lazy_dir}", 1),
+            ("lazy_helper", "${dir}/helper", 1),
+            ("lazy_dir", "${dir}/dir", 1),
+            ("lazy_unused", "${dir}/unused", 0),
+        ] {
+            let (mock, _) = add_mocked_tool(&mut configuration, name, command, *expected_downloads);
+            mocks.push(mock);
+        }
+        let mut cache = Cache::create(configuration).unwrap();
+        cache.init_for_command("lazy_run").unwrap();
+        for name in &["lazy_run", "lazy_helper", "lazy_dir"] {
+            assert!(
+                temp_dir.path().join("tools").join(name).exists(),
+                "{}",
+                name
+            );
+        }
+        assert!(!temp_dir.path().join("tools/lazy_unused").exists());
+        // unknown commands install nothing
+        cache.init_for_command("unknown").unwrap();
+        assert!(!temp_dir.path().join("tools/lazy_unused").exists());
+        for m in mocks {
+            m.assert();
+        }
+    }
+
+    #[test]
+    fn download_tools_by_name_and_tag() {
+        let (mut configuration, temp_dir) = create_configuration();
+        let mut mocks = vec![];
+        for (name, tag, command, expected_downloads) in &[
+            ("subset_jdk", "backend", "${dir}/java", 1),
+            ("subset_maven", "backend", "${dir:subset_jdk} ${dir}/mvn", 1),
+            ("subset_node", "frontend", "${dir}/node", 1),
+            (
+                "subset_yarn",
+                "frontend",
+                "${cmd:subset_node} ${dir}/yarn",
+                0,
+            ),
+        ] {
+            let (mock, tool) =
+                add_mocked_tool(&mut configuration, name, command, *expected_downloads);
+            tool.tags = vec![tag.to_string()];
+            mocks.push(mock);
+        }
+        let mut cache = Cache::create(configuration).unwrap();
+        let error = cache
+            .init_for_tools(&["subset_unknown".to_string()])
+            .expect_err("unknown tool");
+        assert_eq!(error.to_string(), "Tool or tag 'subset_unknown' not found");
+        cache.init_for_tools(&["backend".to_string()]).unwrap();
+        cache.init_for_tools(&["subset_node".to_string()]).unwrap();
+        for name in &["subset_jdk", "subset_maven", "subset_node"] {
+            assert!(
+                temp_dir.path().join("tools").join(name).exists(),
+                "{}",
+                name
+            );
+        }
+        assert!(!temp_dir.path().join("tools/subset_yarn").exists());
+        for m in mocks {
+            m.assert();
+        }
+    }
+
+    #[test]
+    fn install_required_tools_first() {
+        let (mut configuration, temp_dir) = create_configuration();
+        let mut mocks = vec![];
+        for (name, requires) in &[
+            ("requires_maven", vec!["requires_jdk"]),
+            ("requires_jdk", vec!["requires_zlib"]),
+            ("requires_zlib", vec![]),
+            ("requires_node", vec![]),
+        ] {
+            let expected_downloads = if *name == "requires_node" { 0 } else { 1 };
+            let (mock, tool) =
+                add_mocked_tool(&mut configuration, name, "${dir}/bin", expected_downloads);
+            tool.requires = requires.iter().map(|name| name.to_string()).collect();
+            mocks.push(mock);
+        }
+        let tools: Vec<&ToolConfiguration> = configuration.tools.iter().collect();
+        let stage_names: Vec<Vec<&str>> = get_install_stages(&tools)
+            .iter()
+            .map(|stage| stage.iter().map(|tool| tool.name.as_str()).collect())
+            .collect();
+        assert_eq!(
+            stage_names,
+            vec![
+                vec!["requires_zlib", "requires_node"],
+                vec!["requires_jdk"],
+                vec!["requires_maven"]
+            ]
+        );
+        let mut cache = Cache::create(configuration).unwrap();
+        cache.init_for_command("requires_maven").unwrap();
+        for name in &["requires_maven", "requires_jdk", "requires_zlib"] {
+            assert!(
+                temp_dir.path().join("tools").join(name).exists(),
+                "{}",
+                name
+            );
+        }
+        assert!(!temp_dir.path().join("tools/requires_node").exists());
+        for m in mocks {
+            m.assert();
+        }
+    }
+
+    #[test]
+    fn install_stages_of_shared_requirements() {
+        // every tool requires both tools of the previous layer, which is exponential without
+        // memoizing the results
+        let tools: Vec<ToolConfiguration> = (0..64)
+            .map(|index| ToolConfiguration {
+                name: format!("tool{}", index),
+                requires: match index {
+                    0 | 1 => vec![],
+                    _ => vec![
+                        format!("tool{}", index / 2 * 2 - 2),
+                        format!("tool{}", index / 2 * 2 - 1),
+                    ],
+                },
+                ..Default::default()
+            })
+            .collect();
+        crate::config::find_requirement_cycle(&tools, &mut vec!["tool63"], &mut HashSet::new())
+            .unwrap();
+        let tools: Vec<&ToolConfiguration> = tools.iter().collect();
+        let stages = get_install_stages(&tools);
+        assert_eq!(stages.len(), 32);
+        assert!(stages.iter().all(|stage| stage.len() == 2));
+    }
+
+    #[test]
+    fn share_tools_between_projects() {
+        let path = "/cache/tool_shared";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 1);
+        cache.configuration.tools[0].sha256.default = Some(HELLO_WORLD_SHA256.to_string());
+        cache.init().unwrap();
+        let (other_temp_dir, mut other_cache) = create_cache(path);
+        other_cache.configuration.tools[0].sha256.default = Some(HELLO_WORLD_SHA256.to_string());
+        other_cache.shared_store = Some(SharedStore::new(temp_dir.path().join("shared")));
+        // the shared cache is available without network access
+        other_cache.offline = true;
+        other_cache.init().unwrap();
+        let content = read_to_string(other_temp_dir.path().join("tools/foo/1.2.3/foo")).unwrap();
+        assert_eq!(content, "Hello, World!");
+        // the shared cache has its own copy, the files of the first project stay writable
+        #[cfg(unix)]
+        assert!(
+            !std::fs::metadata(temp_dir.path().join("tools/foo/1.2.3/foo"))
+                .unwrap()
+                .permissions()
+                .readonly()
+        );
+        // reinstalling keeps the intact entry, since other projects may still use it
+        other_cache.reinstall(&[], true).unwrap();
+        let content = read_to_string(other_temp_dir.path().join("tools/foo/1.2.3/foo")).unwrap();
+        assert_eq!(content, "Hello, World!");
+        _m.assert();
+    }
+
+    #[test]
+    fn do_not_share_tools_without_checksum() {
+        let path = "/cache/tool_shared_unchecked";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 2);
+        cache.init().unwrap();
+        // the content behind the url may have changed in the meantime
+        let (_other_temp_dir, mut other_cache) = create_cache(path);
+        other_cache.shared_store = Some(SharedStore::new(temp_dir.path().join("shared")));
+        other_cache.init().unwrap();
+        assert!(!temp_dir.path().join("shared/tools").exists());
+        _m.assert();
+    }
+
+    #[test]
+    fn clean_unused_tools() {
+        let (temp_dir, cache) = create_cache("/cache/tool_clean");
+        let tools_dir = temp_dir.path().join("tools");
+        let configured_dir = cache.get_tool_dir(&cache.configuration.tools[0]);
+        std::fs::create_dir_all(&configured_dir).unwrap();
+        std::fs::create_dir_all(tools_dir.join("foo/1.0.0")).unwrap();
+        std::fs::write(tools_dir.join("foo/1.0.0/foo"), "old version").unwrap();
+        std::fs::create_dir_all(tools_dir.join("bar/2.0.0")).unwrap();
+        std::fs::write(tools_dir.join("bar/2.0.0/bar"), "bar").unwrap();
+        // a tmp dir of a process which no longer holds its lock
+        std::fs::create_dir_all(tools_dir.join(".tmp/1-4294967295/foo")).unwrap();
+        std::fs::write(tools_dir.join(".tmp/1-4294967295/foo/foo"), "tmp").unwrap();
+        std::fs::write(tools_dir.join(".tmp/2-4294967295.lock"), "").unwrap();
+        let (current_tmp_dir, _current_tmp_dir_lock) = cache.get_tmp_dir().unwrap();
+        std::fs::create_dir_all(&current_tmp_dir).unwrap();
+        std::fs::create_dir_all(tools_dir.join(".tmp/partial")).unwrap();
+        let kept_partial = tools_dir.join(".tmp/partial/foo-1.2.3-0123456789abcdef.gz");
+        std::fs::write(&kept_partial, "kept").unwrap();
+        std::fs::write(
+            tools_dir.join(".tmp/partial/foo-1.0.0-0123456789abcdef.gz"),
+            "partial",
+        )
+        .unwrap();
+        // a release candidate is a different version, even though it shares the prefix
+        std::fs::write(
+            tools_dir.join(".tmp/partial/foo-1.2.3-rc-0123456789abcdef.gz"),
+            "rc",
+        )
+        .unwrap();
+        let shared_dir = temp_dir.path().join("shared");
+        std::fs::create_dir_all(shared_dir.join("tools/.tmp-1-4294967295")).unwrap();
+        std::fs::write(shared_dir.join("tools/.tmp-1-4294967295/foo"), "store").unwrap();
+        std::fs::create_dir_all(shared_dir.join("tools/0123")).unwrap();
+        // files next to the version dirs are left alone
+        std::fs::write(tools_dir.join("foo/README"), "readme").unwrap();
+        let locks_dir = tools_dir.join(".locks");
+        std::fs::create_dir_all(&locks_dir).unwrap();
+        for lock in &["foo-1.2.3.lock", "foo-1.0.0.lock", "baz-1.0.0.lock"] {
+            std::fs::write(locks_dir.join(lock), "").unwrap();
+        }
+
+        let reclaimed = cache.clean(false, false).unwrap();
+        assert!(configured_dir.exists());
+        assert!(!tools_dir.join("foo/1.0.0").exists());
+        assert!(!tools_dir.join("bar").exists());
+        assert!(tools_dir.join("foo/README").exists());
+        let mut locks: Vec<String> = std::fs::read_dir(&locks_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        locks.sort();
+        assert_eq!(locks, vec!["foo-1.2.3.lock"]);
+        assert!(!tools_dir.join(".tmp/1-4294967295").exists());
+        assert!(!tools_dir.join(".tmp/2-4294967295.lock").exists());
+        assert!(kept_partial.exists());
+        assert_eq!(
+            std::fs::read_dir(tools_dir.join(".tmp/partial"))
+                .unwrap()
+                .count(),
+            1
+        );
+        assert!(!shared_dir.join("tools/.tmp-1-4294967295").exists());
+        assert!(shared_dir.join("tools/0123").exists());
+        assert!(current_tmp_dir.exists());
+        assert_eq!(reclaimed, 11 + 3 + 3 + 7 + 2 + 5);
+
+        // removing all tools of the project keeps the cache shared with other projects
+        let reclaimed = cache.clean(true, false).unwrap();
+        assert_eq!(reclaimed, 4 + 6);
+        assert!(!tools_dir.exists());
+        assert!(shared_dir.exists());
+
+        cache.clean(true, true).unwrap();
+        assert!(!shared_dir.exists());
+    }
+
+    #[test]
+    fn wait_for_concurrent_installation() {
+        let path = "/cache/tool_wait_locked";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 0);
+        let lock_path = temp_dir.path().join("tools/.locks/foo-1.2.3.lock");
+        std::fs::create_dir_all(lock_path.parent().unwrap()).unwrap();
+        let lock = lock_file(&lock_path, std::time::Duration::from_secs(1), &mut || {}).unwrap();
+        cache.configuration.lock_timeout = Some(0);
+        let error = cache.init().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unable to install foo 1.2.3, since another process is still installing it"
+        );
+        // the other process finishes the installation while this one waits
+        cache.configuration.lock_timeout = Some(5);
+        let tool_dir = cache.get_tool_dir(&cache.configuration.tools[0]);
+        let installer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            std::fs::create_dir_all(&tool_dir).unwrap();
+            std::fs::write(tool_dir.join("foo"), "installed concurrently").unwrap();
+            drop(lock);
+        });
+        cache.init().unwrap();
+        installer.join().unwrap();
+        let content = read_to_string(temp_dir.path().join("tools/foo/1.2.3/foo")).unwrap();
+        assert_eq!(content, "installed concurrently");
+        _m.assert();
+    }
+
+    #[test]
+    fn verify_and_reinstall_damaged_tool() {
+        let path = "/cache/tool_damaged";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 2);
+        cache.init().unwrap();
+        // tools which were never installed are left alone
+        let mut other_tool = cache.configuration.tools[0].clone();
+        other_tool.name = "bar".to_string();
+        other_tool.download.default = Some(vec![mockito::server_url() + "/cache/tool_missing"]);
+        cache.configuration.tools.push(other_tool);
+        let tool_path = temp_dir.path().join("tools/foo/1.2.3/foo");
+        assert_eq!(cache.verify().unwrap(), 0);
+        std::fs::write(&tool_path, "Hello, Moon!").unwrap();
+        assert_eq!(cache.verify().unwrap(), 1);
+        assert_eq!(read_to_string(&tool_path).unwrap(), "Hello, World!");
+        assert_eq!(cache.verify().unwrap(), 0);
+        assert!(!temp_dir.path().join("tools/bar").exists());
+        // tools without a manifest are left alone
+        std::fs::remove_file(
+            temp_dir
+                .path()
+                .join("tools/foo/1.2.3")
+                .join(MANIFEST_FILENAME),
+        )
+        .unwrap();
+        assert_eq!(cache.verify().unwrap(), 0);
+        _m.assert();
+    }
+
+    #[test]
+    fn offline_reinstall_keeps_tool() {
+        let path = "/cache/tool_offline_reinstall";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 1);
+        cache.init().unwrap();
+        cache.offline = true;
+        let tool_path = temp_dir.path().join("tools/foo/1.2.3/foo");
+        std::fs::write(&tool_path, "Hello, Moon!").unwrap();
+        let expected_error = "Unable to reinstall tools in offline mode, since they are not in the shared cache:\n  foo 1.2.3";
+        assert_eq!(cache.verify().unwrap_err().to_string(), expected_error);
+        assert_eq!(
+            cache.reinstall(&[], true).unwrap_err().to_string(),
+            expected_error
+        );
+        assert_eq!(read_to_string(&tool_path).unwrap(), "Hello, Moon!");
+        _m.assert();
+    }
+
+    #[test]
+    fn reinstall_tool() {
+        let path = "/cache/tool_reinstall";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 3);
+        cache.init().unwrap();
+        let mut other_tool = cache.configuration.tools[0].clone();
+        other_tool.name = "bar".to_string();
+        other_tool.download.default = Some(vec![mockito::server_url() + "/cache/tool_missing"]);
+        cache.configuration.tools.push(other_tool);
+        let tool_path = temp_dir.path().join("tools/foo/1.2.3/foo");
+        std::fs::write(&tool_path, "Hello, Moon!").unwrap();
+        // only the given tools are installed
+        cache.reinstall(&["foo".to_string()], false).unwrap();
+        assert_eq!(read_to_string(&tool_path).unwrap(), "Hello, World!");
+        assert!(!temp_dir.path().join("tools/bar").exists());
+        cache.configuration.tools.pop();
+        std::fs::write(&tool_path, "Hello, Moon!").unwrap();
+        cache.reinstall(&[], true).unwrap();
+        assert_eq!(read_to_string(&tool_path).unwrap(), "Hello, World!");
+        assert_eq!(
+            cache
+                .reinstall(&["bar".to_string()], false)
+                .unwrap_err()
+                .to_string(),
+            "Tool 'bar' not found"
+        );
+        _m.assert();
+    }
+
+    #[test]
+    fn keep_tool_if_reinstall_fails() {
+        let path = "/cache/tool_reinstall_failed";
+        let (_m, temp_dir, mut cache) = create_mocked_cache(path, 1);
+        cache.init().unwrap();
+        cache.configuration.tools[0].download.default = Some(vec![
+            mockito::server_url() + "/cache/tool_reinstall_missing",
+        ]);
+        cache.reinstall(&[], true).unwrap_err();
+        let tool_path = temp_dir.path().join("tools/foo/1.2.3/foo");
+        assert_eq!(read_to_string(&tool_path).unwrap(), "Hello, World!");
+    }
+
+    fn create_signed_cache(path: &str, content: &[u8]) -> (TempDir, Cache, Vec<mockito::Mock>) {
+        let mocks = vec![
+            mock("GET", path)
+                .with_status(200)
+                .with_body(content)
+                .create(),
+            mock("GET", (path.to_string() + ".minisig").as_str())
+                .with_status(200)
+                .with_body(crate::signature::tests::HELLO_WORLD_SIGNATURE)
+                .create(),
+        ];
+        let (temp_dir, mut cache) = create_cache(path);
+        cache.configuration.tools[0].signature = Some(SignatureConfiguration {
+            url: "${url}.minisig".to_string(),
+            public_key: crate::signature::tests::PUBLIC_KEY.to_string(),
+        });
+        (temp_dir, cache, mocks)
+    }
+
+    #[test]
+    fn download_with_valid_signature() {
+        let path = "/cache/tool_signed";
+        let (temp_dir, mut cache, _mocks) = create_signed_cache(path, b"Hello, World!");
+        cache.init().unwrap();
+        let path = temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .join("foo");
+        let content = read_to_string(path).expect("File foo should exist");
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[test]
+    fn download_with_invalid_signature() {
+        let path = "/cache/tool_signed_tampered";
+        let (temp_dir, mut cache, _mocks) = create_signed_cache(path, b"Hello, World?");
+        let error = cache.init().expect_err("signature error expected");
+        assert_eq!(
+            format!("{:#}", error),
+            format!(
+                "Unable to verify download {0} of foo 1.2.3: Invalid signature {0}.minisig: Signature verification failed: The signature verification failed",
+                mockito::server_url() + path
+            )
+        );
+        assert!(!temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .exists());
+    }
+
     fn verify_hello_world_txt(path: &str) -> TempDir {
         let (temp_dir, mut cache) = create_cache(path);
         cache.init().unwrap();
@@ -525,24 +2405,68 @@ mod tests {
         temp_dir
     }
 
+    // A cache with a single tool, which is served with the given number of downloads
+    fn create_mocked_cache(
+        path: &str,
+        expected_downloads: usize,
+    ) -> (mockito::Mock, TempDir, Cache) {
+        let mock = mock("GET", path)
+            .with_status(200)
+            .with_body(b"Hello, World!")
+            .expect(expected_downloads)
+            .create();
+        let (temp_dir, cache) = create_cache(path);
+        (mock, temp_dir, cache)
+    }
+
     fn create_cache(path: &str) -> (TempDir, Cache) {
         let (mut configuration, temp_dir) = create_configuration();
         configuration.tools.push(ToolConfiguration {
             name: "foo".to_string(),
             version: "1.2.3".to_string(),
             download: DownloadUrls {
-                default: Some(mockito::server_url() + path),
+                default: Some(vec![mockito::server_url() + path]),
                 linux: None,
                 windows: None,
             },
             commands: HashMap::new(),
             env: HashMap::new(),
             strip_directories: 1,
+            ..Default::default()
         });
         let cache = Cache::create(configuration).unwrap();
         (temp_dir, cache)
     }
 
+    // Add a tool with a command of the same name, served by a mock expecting the given downloads
+    fn add_mocked_tool<'a>(
+        configuration: &'a mut Configuration,
+        name: &str,
+        command: &str,
+        expected_downloads: usize,
+    ) -> (mockito::Mock, &'a mut ToolConfiguration) {
+        let path = format!("/cache/{}", name);
+        let mock = mock("GET", path.as_str())
+            .with_status(200)
+            .with_body(name)
+            .expect(expected_downloads)
+            .create();
+        let mut commands = HashMap::new();
+        commands.insert(name.to_string(), command.to_string());
+        configuration.tools.push(ToolConfiguration {
+            name: name.to_string(),
+            version: "1.2.3".to_string(),
+            download: DownloadUrls {
+                default: Some(vec![mockito::server_url() + &path]),
+                linux: None,
+                windows: None,
+            },
+            commands,
+            ..Default::default()
+        });
+        (mock, configuration.tools.last_mut().unwrap())
+    }
+
     #[test]
     fn download_tar_gz() {
         let path = "/cache/tool.tar.gz";
@@ -561,6 +2485,364 @@ mod tests {
         verify_hello_world_txt(path);
     }
 
+    fn create_hello_world_tar() -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_path("foo/hello_world.txt").unwrap();
+        let content = b"Hello, World!";
+        header.set_size(content.len() as u64);
+        header.set_cksum();
+        ar.append(&header, Cursor::new(content)).unwrap();
+        ar.into_inner().unwrap()
+    }
+
+    #[test]
+    fn download_tar() {
+        let path = "/cache/tool.tar";
+        let data = create_hello_world_tar();
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        verify_hello_world_txt(path);
+    }
+
+    #[test]
+    fn download_tar_xz() {
+        let path = "/cache/tool.tar.xz";
+        let mut data = Vec::new();
+        lzma_rs::xz_compress(&mut Cursor::new(create_hello_world_tar()), &mut data).unwrap();
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        verify_hello_world_txt(path);
+    }
+
+    #[test]
+    fn download_tar_bz2() {
+        let path = "/cache/tool.tar.bz2";
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        encoder.write_all(&create_hello_world_tar()).unwrap();
+        let data = encoder.finish().unwrap();
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        verify_hello_world_txt(path);
+    }
+
+    #[test]
+    fn download_tar_zst() {
+        let path = "/cache/tool.tar.zst";
+        let data = ruzstd::encoding::compress_to_vec(
+            Cursor::new(create_hello_world_tar()),
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        verify_hello_world_txt(path);
+    }
+
+    #[test]
+    fn download_unsupported_archive_type() {
+        let path = "/cache/tool.7z";
+        let (_temp_dir, mut cache) = create_cache(path);
+        let error = cache.init().expect_err("unsupported archive type expected");
+        assert_eq!(
+            format!("{:#}", error),
+            "Unable to download foo 1.2.3: Unsupported archive type '.7z' for file tool.7z"
+        );
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn download_tar_gz_without_extension() {
+        let path = "/cache/download?id=123";
+        let data = gzip(&create_hello_world_tar());
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        verify_hello_world_txt(path);
+    }
+
+    #[test]
+    fn download_with_format_override() {
+        let path = "/cache/tool.7z";
+        let data = gzip(&create_hello_world_tar());
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        let (temp_dir, mut cache) = create_cache(path);
+        cache.configuration.tools[0].format = Some(ArchiveType::TarGz);
+        cache.init().unwrap();
+        let path = temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .join("hello_world.txt");
+        let content = read_to_string(path).expect("File hello_world.txt should exist");
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[test]
+    fn download_gz_single_file() {
+        let path = "/cache/tool.gz";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body(gzip(b"Hello, World!"))
+            .create();
+        let (temp_dir, mut cache) = create_cache(path);
+        cache.init().unwrap();
+        let path = temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .join("foo");
+        let content = read_to_string(path).expect("File foo should exist");
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[test]
+    fn download_xz_single_file() {
+        let path = "/cache/tool.xz";
+        let mut data = Vec::new();
+        lzma_rs::xz_compress(&mut Cursor::new(b"Hello, World!"), &mut data).unwrap();
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        let (temp_dir, mut cache) = create_cache(path);
+        cache.init().unwrap();
+        let content = read_to_string(temp_dir.path().join("tools/foo/1.2.3/foo")).unwrap();
+        assert_eq!(content, "Hello, World!");
+    }
+
+    #[test]
+    fn resume_partial_download() {
+        let path = "/cache/tool_partial";
+        let _m = mock("GET", path)
+            .match_header("range", "bytes=7-")
+            .with_status(206)
+            .with_header("content-range", "bytes 7-12/13")
+            .with_body("World!")
+            .create();
+        let (temp_dir, mut cache) = create_cache(path);
+        let url = mockito::server_url() + path;
+        let partial_path =
+            cache.get_partial_download_path(&cache.configuration.tools[0], &url, "tool_partial");
+        std::fs::create_dir_all(partial_path.parent().unwrap()).unwrap();
+        std::fs::write(&partial_path, "Hello, ").unwrap();
+        cache.init().unwrap();
+        let path = temp_dir
+            .path()
+            .join("tools")
+            .join("foo")
+            .join("1.2.3")
+            .join("foo");
+        let content = read_to_string(path).expect("File foo should exist");
+        assert_eq!(content, "Hello, World!");
+        assert!(!partial_path.exists(), "partial download should be removed");
+    }
+
+    fn create_parallel_cache(tool_count: usize) -> (TempDir, Cache) {
+        let (mut configuration, temp_dir) = create_configuration();
+        configuration.download_workers = Some(3);
+        for i in 0..tool_count {
+            configuration.tools.push(ToolConfiguration {
+                name: format!("parallel{}", i),
+                version: "1.2.3".to_string(),
+                download: DownloadUrls {
+                    default: Some(vec![format!(
+                        "{}/cache/parallel{}",
+                        mockito::server_url(),
+                        i
+                    )]),
+                    linux: None,
+                    windows: None,
+                },
+                ..Default::default()
+            });
+        }
+        let cache = Cache::create(configuration).unwrap();
+        (temp_dir, cache)
+    }
+
+    #[test]
+    fn download_tools_in_parallel() {
+        let mocks: Vec<_> = (0..5)
+            .map(|i| {
+                mock("GET", format!("/cache/parallel{}", i).as_str())
+                    .with_status(200)
+                    .with_body(format!("tool {}", i))
+                    .create()
+            })
+            .collect();
+        let (temp_dir, mut cache) = create_parallel_cache(5);
+        cache.init().unwrap();
+        for (i, mock) in mocks.iter().enumerate() {
+            mock.assert();
+            let path = temp_dir
+                .path()
+                .join("tools")
+                .join(format!("parallel{}", i))
+                .join("1.2.3")
+                .join(format!("parallel{}", i));
+            let content = read_to_string(path).expect("Tool file should exist");
+            assert_eq!(content, format!("tool {}", i));
+        }
+    }
+
+    #[test]
+    fn download_tools_in_parallel_with_failure() {
+        let _m0 = mock("GET", "/cache/parallel0")
+            .with_status(200)
+            .with_body("tool 0")
+            .create();
+        let _m1 = mock("GET", "/cache/parallel1").with_status(404).create();
+        let (_temp_dir, mut cache) = create_parallel_cache(2);
+        let error = cache.init().expect_err("download should fail");
+        assert!(
+            error.to_string().starts_with(&format!(
+                "Unable to download {}/cache/parallel1 to ",
+                mockito::server_url()
+            )),
+            "{:#}",
+            error
+        );
+    }
+
+    // Creates a tar.gz archive with unvalidated entry paths and link targets.
+    // The third tuple element is the file content or the link target for links.
+    fn create_raw_tar_gz(entries: &[(&str, tar::EntryType, &str)]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut ar = tar::Builder::new(encoder);
+        for (path, entry_type, data) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            let content: &[u8] = if entry_type.is_file() || entry_type.is_dir() {
+                data.as_bytes()
+            } else {
+                header.as_old_mut().linkname[..data.len()].copy_from_slice(data.as_bytes());
+                &[]
+            };
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            ar.append(&header, Cursor::new(content)).unwrap();
+        }
+        ar.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn extract_raw_tar_gz(path: &str, entries: &[(&str, tar::EntryType, &str)]) -> Result<TempDir> {
+        let data = create_raw_tar_gz(entries);
+        let _m = mock("GET", path).with_status(200).with_body(data).create();
+        let (temp_dir, mut cache) = create_cache(path);
+        cache.init().map(|_| temp_dir)
+    }
+
+    fn assert_extraction_error(
+        path: &str,
+        entries: &[(&str, tar::EntryType, &str)],
+        message: &str,
+    ) {
+        let error = extract_raw_tar_gz(path, entries).expect_err("extraction error expected");
+        assert_eq!(format!("{:#}", error), message);
+    }
+
+    #[test]
+    fn download_tar_gz_with_links() {
+        let temp_dir = extract_raw_tar_gz(
+            "/cache/tool_links.tar.gz",
+            &[
+                (
+                    "foo/lib/hello_world.txt",
+                    tar::EntryType::Regular,
+                    "Hello, World!",
+                ),
+                (
+                    "foo/hello_world.txt",
+                    tar::EntryType::Link,
+                    "foo/lib/hello_world.txt",
+                ),
+                (
+                    "foo/bin/hello",
+                    tar::EntryType::Symlink,
+                    "../lib/hello_world.txt",
+                ),
+            ],
+        )
+        .unwrap();
+        let tool_dir = temp_dir.path().join("tools").join("foo").join("1.2.3");
+        let content = read_to_string(tool_dir.join("hello_world.txt")).unwrap();
+        assert_eq!(content, "Hello, World!");
+        #[cfg(target_family = "unix")]
+        {
+            let content = read_to_string(tool_dir.join("bin").join("hello")).unwrap();
+            assert_eq!(content, "Hello, World!");
+        }
+    }
+
+    #[test]
+    fn download_tar_gz_with_parent_dir_path() {
+        assert_extraction_error(
+            "/cache/tool_parent_dir.tar.gz",
+            &[("foo/../../evil.txt", tar::EntryType::Regular, "evil")],
+            "Refusing to extract tar entry \"foo/../../evil.txt\": path contains '..'",
+        );
+    }
+
+    #[test]
+    fn download_tar_gz_with_absolute_path() {
+        assert_extraction_error(
+            "/cache/tool_absolute.tar.gz",
+            &[("/tmp/evil.txt", tar::EntryType::Regular, "evil")],
+            "Refusing to extract tar entry \"/tmp/evil.txt\": path is absolute",
+        );
+    }
+
+    #[test]
+    fn download_tar_gz_with_absolute_symlink() {
+        assert_extraction_error(
+            "/cache/tool_absolute_symlink.tar.gz",
+            &[("foo/passwd", tar::EntryType::Symlink, "/etc/passwd")],
+            "Refusing to extract tar entry \"foo/passwd\" linking to \"/etc/passwd\": link target is absolute",
+        );
+    }
+
+    #[test]
+    fn download_tar_gz_with_escaping_symlink() {
+        assert_extraction_error(
+            "/cache/tool_escaping_symlink.tar.gz",
+            &[("foo/bin/evil", tar::EntryType::Symlink, "../../outside")],
+            "Refusing to extract tar entry \"foo/bin/evil\" linking to \"../../outside\": link target is outside the extraction directory",
+        );
+    }
+
+    #[test]
+    fn download_tar_gz_with_symlink_traversal() {
+        assert_extraction_error(
+            "/cache/tool_symlink_traversal.tar.gz",
+            &[
+                ("foo/bin/lib", tar::EntryType::Symlink, "."),
+                ("foo/bin/evil", tar::EntryType::Symlink, "lib/../.."),
+            ],
+            "Refusing to extract tar entry \"foo/bin/evil\" linking to \"lib/../..\": link target contains '..' after a directory name",
+        );
+    }
+
+    #[test]
+    fn download_tar_gz_with_link_through_symlinked_directory() {
+        assert_extraction_error(
+            "/cache/tool_symlinked_directory.tar.gz",
+            &[
+                ("foo/current", tar::EntryType::Symlink, "."),
+                ("foo/current/evil", tar::EntryType::Symlink, "../outside"),
+            ],
+            "Refusing to extract tar entry \"foo/current/evil\" linking to \"../outside\": link target is outside the extraction directory",
+        );
+    }
+
+    #[test]
+    fn download_tar_gz_with_escaping_hard_link() {
+        assert_extraction_error(
+            "/cache/tool_escaping_hard_link.tar.gz",
+            &[("foo/evil", tar::EntryType::Link, "../../etc/passwd")],
+            "Refusing to extract tar entry \"foo/evil\" linking to \"../../etc/passwd\": path contains '..'",
+        );
+    }
+
     #[test]
     fn download_tar_gz_with_too_many_directories_stripped() {
         let path = "/cache/tool.tar.gz";
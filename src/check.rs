@@ -0,0 +1,322 @@
+use crate::cache::CommandTemplate;
+use crate::config::{get_unknown_requirements, Configuration, ToolConfiguration};
+use crate::template::get_template_names;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+// Templates supported in signature urls, ${version} is already replaced when reading the config
+const SIGNATURE_TEMPLATES: &[&str] = &["url"];
+
+// Find mistakes in the configuration which would otherwise only show up when running a command
+pub fn check_configuration(configuration: &Configuration) -> Vec<String> {
+    let mut problems = vec![];
+    let mut tool_names = HashSet::new();
+    let mut command_tools: HashMap<&str, &str> = HashMap::new();
+    // each cycle is reported once, on the first of its tools
+    let mut reported_cycles: HashSet<BTreeSet<&str>> = HashSet::new();
+    for tool in &configuration.tools {
+        let mut report = |field: String, problem: String| {
+            problems.push(format!("tool '{}', {}: {}", tool.name, field, problem));
+        };
+        if !tool_names.insert(tool.name.as_str()) {
+            report("name".to_string(), "Duplicate tool name".to_string());
+        }
+        check_download_urls(tool, &mut report);
+        for required in get_unknown_requirements(&configuration.tools, tool) {
+            report(
+                "requires".to_string(),
+                format!("Unknown tool '{}'", required),
+            );
+        }
+        if let Some(cycle) = find_cycle_through(&configuration.tools, tool) {
+            if reported_cycles.insert(cycle.iter().copied().collect()) {
+                report(
+                    "requires".to_string(),
+                    format!("Cyclic tool requirements: {}", cycle.join(" -> ")),
+                );
+            }
+        }
+        let mut commands: Vec<_> = tool.commands.iter().collect();
+        commands.sort();
+        for (command, command_line) in commands {
+            let field = format!("command '{}'", command);
+            match command_tools.get(command.as_str()) {
+                Some(other_tool) if *other_tool != tool.name => report(
+                    field.clone(),
+                    format!("Command is also provided by tool '{}'", other_tool),
+                ),
+                _ => {
+                    command_tools.insert(command.as_str(), tool.name.as_str());
+                }
+            }
+            for problem in check_command_templates(configuration, command_line) {
+                report(field.clone(), problem);
+            }
+        }
+        let mut env: Vec<_> = tool.env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            for problem in check_command_templates(configuration, value) {
+                report(format!("env '{}'", key), problem);
+            }
+        }
+    }
+    problems
+}
+
+// Shortest requirement cycle through the tool, like [a, b, a]
+fn find_cycle_through<'a>(
+    tools: &'a [ToolConfiguration],
+    tool: &'a ToolConfiguration,
+) -> Option<Vec<&'a str>> {
+    let start = tool.name.as_str();
+    // breadth first search for a path from the tool back to itself
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(name) = queue.pop_front() {
+        let current = match tools.iter().find(|tool| tool.name == name) {
+            Some(current) => current,
+            None => continue,
+        };
+        for required in &current.requires {
+            if required == start {
+                let mut cycle = vec![start];
+                let mut step = name;
+                while step != start {
+                    cycle.push(step);
+                    step = previous[step];
+                }
+                cycle.push(start);
+                cycle.reverse();
+                return Some(cycle);
+            }
+            if !previous.contains_key(required.as_str()) {
+                previous.insert(required, name);
+                queue.push_back(required);
+            }
+        }
+    }
+    None
+}
+
+fn check_download_urls(tool: &ToolConfiguration, report: &mut dyn FnMut(String, String)) {
+    let download = &tool.download;
+    if download.default.is_none() {
+        for (platform, urls) in &[("linux", &download.linux), ("windows", &download.windows)] {
+            if urls.is_none() {
+                report(
+                    "download".to_string(),
+                    format!("No url for {} and no default url", platform),
+                );
+            }
+        }
+    }
+    for (platform, urls) in download.entries() {
+        let field = format!("download {}", platform);
+        if urls.is_empty() {
+            report(field.clone(), "Empty list of urls".to_string());
+        }
+        for url in urls {
+            for problem in check_templates(url, |name| Err(unsupported_template(name))) {
+                report(field.clone(), problem);
+            }
+        }
+    }
+    if let Some(signature) = &tool.signature {
+        let problems = check_templates(&signature.url, |name| {
+            if SIGNATURE_TEMPLATES.contains(&name) {
+                Ok(())
+            } else {
+                Err(unsupported_template(name))
+            }
+        });
+        for problem in problems {
+            report("signature url".to_string(), problem);
+        }
+    }
+}
+
+fn check_command_templates(configuration: &Configuration, string: &str) -> Vec<String> {
+    check_templates(string, |name| match CommandTemplate::parse(name) {
+        Some(CommandTemplate::Command(command)) => {
+            if configuration
+                .tools
+                .iter()
+                .any(|tool| tool.commands.contains_key(command))
+            {
+                Ok(())
+            } else {
+                Err(format!("Unknown command '{}' in '${{{}}}'", command, name))
+            }
+        }
+        Some(CommandTemplate::ToolDir(tool_name)) => {
+            if configuration
+                .tools
+                .iter()
+                .any(|tool| tool.name == tool_name)
+            {
+                Ok(())
+            } else {
+                Err(format!("Unknown tool '{}' in '${{{}}}'", tool_name, name))
+            }
+        }
+        Some(_) => Ok(()),
+        None => Err(unsupported_template(name)),
+    })
+}
+
+fn check_templates(string: &str, check_name: impl Fn(&str) -> Result<(), String>) -> Vec<String> {
+    match get_template_names(string) {
+        Ok(names) => names
+            .iter()
+            .filter_map(|name| check_name(name).err())
+            .collect(),
+        Err(error) => vec![format!("{:#}", error)],
+    }
+}
+
+fn unsupported_template(name: &str) -> String {
+    format!("Unsupported template '${{{}}}'", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_unvalidated_config;
+    use std::io::Cursor;
+
+    fn check(yaml: &'static str) -> Vec<String> {
+        let cursor = Cursor::new(yaml.as_bytes());
+        let configuration = read_unvalidated_config(Box::new(cursor), "root/foo.yaml").unwrap();
+        check_configuration(&configuration)
+    }
+
+    #[test]
+    fn valid_configuration() {
+        assert_eq!(
+            check(
+                r#"
+tools:
+  - name: openjdk
+    version: 11.0.2
+    download:
+      linux: https://example.com/openjdk-linux.tar.gz
+      windows: https://example.com/openjdk-windows.zip
+    signature:
+      url: ${url}.minisig
+      public_key: key
+    commands:
+      java: ${dir}/bin/java ${linux:-Xss4m}${windows:-Xss8m}
+    env:
+      JAVA_HOME: ${dir}
+  - name: maven
+    version: 3.6.3
+    download:
+      default: https://example.com/maven.tar.gz
+    commands:
+      mvn: ${dir}/bin/mvn -v ${version}
+    env:
+      JAVA_HOME: ${dir:openjdk}
+      JAVA: ${cmd:java}
+      HOME: ${env:HOME}
+        "#
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn report_all_problems() {
+        assert_eq!(
+            check(
+                r#"
+tools:
+  - name: foo
+    version: 1.2.3
+    download:
+      linux: https://example.com/foo-${arch}.tar.gz
+    signature:
+      url: ${url}.${sig}
+      public_key: key
+    commands:
+      foo: ${dir}/foo ${cmd:bar}
+      fizz: ${dir}/fizz ${dir:baz} ${unknown:x}
+    env:
+      FOO: ${dir
+  - name: foo
+    version: 2.0.0
+    download:
+      default: []
+    commands:
+      foo: ${dir}/foo
+        "#
+            ),
+            vec![
+                "tool 'foo', download: No url for windows and no default url",
+                "tool 'foo', download linux: Unsupported template '${arch}'",
+                "tool 'foo', signature url: Unsupported template '${sig}'",
+                "tool 'foo', command 'fizz': Unknown tool 'baz' in '${dir:baz}'",
+                "tool 'foo', command 'fizz': Unsupported template '${unknown:x}'",
+                "tool 'foo', command 'foo': Unknown command 'bar' in '${cmd:bar}'",
+                "tool 'foo', env 'FOO': Unclosed template string in template '${dir', you may be missing a closing '}'",
+                "tool 'foo', name: Duplicate tool name",
+                "tool 'foo', download default: Empty list of urls",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_commands() {
+        assert_eq!(
+            check(
+                r#"
+tools:
+  - name: node
+    version: 12.0.0
+    download:
+      default: https://example.com/node.tar.gz
+    commands:
+      node: ${dir}/bin/node
+      npm: ${dir}/bin/npm
+  - name: npm
+    version: 6.0.0
+    download:
+      default: https://example.com/npm.tar.gz
+    commands:
+      npm: ${dir}/bin/npm
+        "#
+            ),
+            vec!["tool 'npm', command 'npm': Command is also provided by tool 'node'"]
+        );
+    }
+
+    #[test]
+    fn invalid_requirements() {
+        assert_eq!(
+            check(
+                r#"
+tools:
+  - name: maven
+    version: 3.6.3
+    requires: [openjkd, node]
+    download:
+      default: https://example.com/maven.tar.gz
+  - name: node
+    version: 12.0.0
+    requires: [npm]
+    download:
+      default: https://example.com/node.tar.gz
+  - name: npm
+    version: 6.0.0
+    requires: [node]
+    download:
+      default: https://example.com/npm.tar.gz
+        "#
+            ),
+            vec![
+                "tool 'maven', requires: Unknown tool 'openjkd'",
+                "tool 'node', requires: Cyclic tool requirements: node -> npm -> node",
+            ]
+        );
+    }
+}
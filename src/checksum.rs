@@ -0,0 +1,76 @@
+use crate::{bail, Result};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::Path;
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Unable to open file {:?} for hashing", path))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Unable to read file {:?} for hashing", path))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+pub fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        bail!(
+            "SHA-256 checksum mismatch: expected {}, actual {}",
+            expected.trim(),
+            actual
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const HELLO_WORLD_SHA256: &str =
+        "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f";
+
+    fn create_temp_file(content: &[u8]) -> tempfile::TempPath {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content).unwrap();
+        file.into_temp_path()
+    }
+
+    #[test]
+    fn hash_file() {
+        let path = create_temp_file(b"Hello, World!");
+        assert_eq!(sha256_file(&path).unwrap(), HELLO_WORLD_SHA256);
+    }
+
+    #[test]
+    fn hash_bytes() {
+        assert_eq!(sha256_hex(b"Hello, World!"), HELLO_WORLD_SHA256);
+    }
+
+    #[test]
+    fn verify_matching_checksum() {
+        let path = create_temp_file(b"Hello, World!");
+        verify_sha256(&path, &HELLO_WORLD_SHA256.to_uppercase()).unwrap();
+    }
+
+    #[test]
+    fn verify_mismatching_checksum() {
+        let path = create_temp_file(b"Hello, World?");
+        let error = verify_sha256(&path, HELLO_WORLD_SHA256).expect_err("mismatch expected");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "SHA-256 checksum mismatch: expected {}, actual {}",
+                HELLO_WORLD_SHA256,
+                sha256_file(&path).unwrap()
+            )
+        );
+    }
+}
@@ -1,6 +1,8 @@
-use crate::Result;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::archive::ArchiveType;
+use crate::{bail, Result};
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
@@ -10,6 +12,27 @@ pub const CONFIG_FILENAME: &str = ".tool-tool.v1.yaml";
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Configuration {
     pub cache_dir: Option<String>,
+    // store for tools shared between projects, defaults to a directory in the user cache
+    #[serde(default)]
+    pub shared_cache_dir: Option<String>,
+    // number of tools downloaded in parallel
+    #[serde(default)]
+    pub download_workers: Option<usize>,
+    // number of retries after transient download failures
+    #[serde(default)]
+    pub download_retries: Option<u32>,
+    // seconds to wait for another process installing the same tool
+    #[serde(default)]
+    pub lock_timeout: Option<u64>,
+    // proxy for downloads if none is set in the environment, e.g. http://proxy:3128
+    #[serde(default)]
+    pub proxy: Option<String>,
+    // credentials for downloads from hosts requiring authentication
+    #[serde(default)]
+    pub credentials: Vec<CredentialConfiguration>,
+    // url prefixes mapped to mirror prefixes, which are tried before the original url
+    #[serde(default)]
+    pub mirrors: BTreeMap<String, String>,
     pub tools: Vec<ToolConfiguration>,
     #[serde(skip_deserializing)]
     pub configuration_files: Vec<String>,
@@ -19,7 +42,22 @@ pub struct Configuration {
 pub struct ToolConfiguration {
     pub name: String,
     pub version: String,
+    // groups the tool belongs to, which can be downloaded together with tt --download <tag>
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // tools installed before this tool, e.g. a JDK referenced via ${dir:openjdk}
+    #[serde(default)]
+    pub requires: Vec<String>,
     pub download: DownloadUrls,
+    // archive format of the download, detected from the url or content if not set
+    #[serde(default)]
+    pub format: Option<ArchiveType>,
+    // expected SHA-256 checksums of the downloaded artifacts
+    #[serde(default)]
+    pub sha256: Checksums,
+    // detached signature to verify the downloaded artifacts against
+    #[serde(default)]
+    pub signature: Option<SignatureConfiguration>,
     #[serde(default)]
     pub commands: HashMap<String, String>,
     #[serde(default)]
@@ -35,12 +73,110 @@ fn default_strip_directories() -> usize {
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DownloadUrls {
+    // either a single url or a list of a primary url followed by mirrors, tried in order
+    #[serde(default, deserialize_with = "deserialize_urls")]
+    pub default: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_urls")]
+    pub linux: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_urls")]
+    pub windows: Option<Vec<String>>,
+}
+
+impl DownloadUrls {
+    // all configured urls, keyed by platform name
+    pub fn entries(&self) -> Vec<(&'static str, &[String])> {
+        let mut entries = vec![];
+        if let Some(urls) = &self.default {
+            entries.push(("default", urls.as_slice()));
+        }
+        if let Some(urls) = &self.linux {
+            entries.push(("linux", urls.as_slice()));
+        }
+        if let Some(urls) = &self.windows {
+            entries.push(("windows", urls.as_slice()));
+        }
+        entries
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UrlList {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+fn deserialize_urls<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<String>>, D::Error> {
+    Ok(
+        Option::<UrlList>::deserialize(deserializer)?.map(|urls| match urls {
+            UrlList::Single(url) => vec![url],
+            UrlList::Multiple(urls) => urls,
+        }),
+    )
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksums {
     pub default: Option<String>,
     pub linux: Option<String>,
     pub windows: Option<String>,
 }
 
+impl Checksums {
+    // checksum of the download for the given platform name, falling back to the default
+    pub fn get(&self, platform: &str) -> Option<&str> {
+        let checksum = match platform {
+            "linux" => self.linux.as_deref(),
+            "windows" => self.windows.as_deref(),
+            _ => None,
+        };
+        checksum.or(self.default.as_deref())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureConfiguration {
+    // url of the minisign signature, ${url} is replaced with the download url
+    pub url: String,
+    pub public_key: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialConfiguration {
+    pub host: String,
+    // environment variable containing a bearer token
+    #[serde(default)]
+    pub token_env: Option<String>,
+    // environment variables containing username and password for basic authentication
+    #[serde(default)]
+    pub username_env: Option<String>,
+    #[serde(default)]
+    pub password_env: Option<String>,
+    // credentials are looked up in ~/.netrc if no environment variables are given
+}
+
 pub fn get_config(binary_name: &str) -> Result<Configuration> {
+    let config_path = find_config_path(binary_name);
+    verbose!("Reading configuration from {:?}", config_path);
+    read_config(
+        Box::new(File::open(&config_path)?),
+        &config_path.to_string_lossy(),
+    )
+}
+
+// Read the configuration without validating the tool requirements, to report all problems at once
+pub fn get_unvalidated_config(binary_name: &str) -> Result<Configuration> {
+    let config_path = find_config_path(binary_name);
+    verbose!("Reading configuration from {:?}", config_path);
+    read_unvalidated_config(
+        Box::new(File::open(&config_path)?),
+        &config_path.to_string_lossy(),
+    )
+}
+
+fn find_config_path(binary_name: &str) -> PathBuf {
     let binary_path = PathBuf::from(binary_name);
     let mut parent_directory = binary_path;
     let mut config_path: PathBuf;
@@ -60,20 +196,25 @@ pub fn get_config(binary_name: &str) -> Result<Configuration> {
             })
             .to_path_buf();
     }
-    let config_path = parent_directory.join(CONFIG_FILENAME);
-    verbose!("Reading configuration from {:?}", config_path);
-    read_config(
-        Box::new(File::open(&config_path)?),
-        &config_path.to_string_lossy(),
-    )
+    parent_directory.join(CONFIG_FILENAME)
 }
 
-fn read_config(mut reader: Box<dyn Read>, path: &str) -> Result<Configuration> {
+fn read_config(reader: Box<dyn Read>, path: &str) -> Result<Configuration> {
+    let configuration = read_unvalidated_config(reader, path)?;
+    validate_requirements(&configuration.tools)
+        .with_context(|| format!("Invalid configuration {}", path))?;
+    Ok(configuration)
+}
+
+pub fn read_unvalidated_config(mut reader: Box<dyn Read>, path: &str) -> Result<Configuration> {
     let mut configuration: Configuration = serde_yaml::from_reader(reader.as_mut())?;
     for tool in &mut configuration.tools {
         replace_templates(&mut tool.download.default, &tool.version);
         replace_templates(&mut tool.download.linux, &tool.version);
         replace_templates(&mut tool.download.windows, &tool.version);
+        if let Some(signature) = &mut tool.signature {
+            signature.url = signature.url.replace("${version}", &tool.version);
+        }
         // Add default command
         if tool.commands.is_empty() {
             tool.commands.insert(tool.name.clone(), tool.name.clone());
@@ -101,9 +242,64 @@ fn read_config(mut reader: Box<dyn Read>, path: &str) -> Result<Configuration> {
     Ok(configuration)
 }
 
-fn replace_templates(string: &mut Option<String>, version: &str) {
-    if let Some(inner) = string {
-        *string = Some(inner.replace("${version}", version));
+// Required tools must be configured and must not require each other in a cycle
+fn validate_requirements(tools: &[ToolConfiguration]) -> Result<()> {
+    for tool in tools {
+        if let Some(required) = get_unknown_requirements(tools, tool).first() {
+            bail!("Tool '{}' requires unknown tool '{}'", tool.name, required);
+        }
+    }
+    let mut checked = HashSet::new();
+    for tool in tools {
+        find_requirement_cycle(tools, &mut vec![tool.name.as_str()], &mut checked)?;
+    }
+    Ok(())
+}
+
+pub fn get_unknown_requirements<'a>(
+    tools: &[ToolConfiguration],
+    tool: &'a ToolConfiguration,
+) -> Vec<&'a str> {
+    tool.requires
+        .iter()
+        .filter(|required| !tools.iter().any(|other| &other.name == *required))
+        .map(String::as_str)
+        .collect()
+}
+
+// Checked tools have no cycles among their requirements, so they are not visited again
+pub fn find_requirement_cycle<'a>(
+    tools: &'a [ToolConfiguration],
+    chain: &mut Vec<&'a str>,
+    checked: &mut HashSet<&'a str>,
+) -> Result<()> {
+    let name = *chain.last().expect("Chain should not be empty");
+    if checked.contains(name) {
+        return Ok(());
+    }
+    let tool = match tools.iter().find(|tool| tool.name == name) {
+        Some(tool) => tool,
+        None => return Ok(()),
+    };
+    for required in &tool.requires {
+        if let Some(start) = chain.iter().position(|name| name == required) {
+            bail!(
+                "Cyclic tool requirements: {} -> {}",
+                chain[start..].join(" -> "),
+                required
+            );
+        }
+        chain.push(required);
+        find_requirement_cycle(tools, chain, checked)?;
+        chain.pop();
+    }
+    checked.insert(name);
+    Ok(())
+}
+
+fn replace_templates(urls: &mut Option<Vec<String>>, version: &str) {
+    for url in urls.iter_mut().flatten() {
+        *url = url.replace("${version}", version);
     }
 }
 
@@ -140,20 +336,95 @@ tools:
     fn with_commands() {
         verify_config(
             r#"
+shared_cache_dir: /var/cache/tool-tool
+download_workers: 2
+download_retries: 5
+lock_timeout: 30
+proxy: http://proxy:3128
+credentials:
+  - host: artifacts.example.com
+    token_env: ARTIFACTS_TOKEN
+  - host: internal.example.com
+mirrors:
+  "https://github.com/": "https://mirror.example.com/github/"
 tools:
   - name: xyz
     version: 0.17.0
+    tags: [backend]
+    requires: [abc]
     strip_directories: 0
     download:
       default: https://default.tar.gz
-      windows: https://windows.tar.gz
+      windows:
+        - https://windows-${version}.tar.gz
+        - https://mirror.example.com/windows-${version}.tar.gz
+    format: tar.gz
+    sha256:
+      windows: 0123456789abcdef
+    signature:
+      url: ${url}.${version}.minisig
+      public_key: RWQBAgMEBQYHCAOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4
     commands:
       foo: bar
       fizz: ${dir}/buzz
+  - name: abc
+    version: 1.0.0
+    download:
+      default: https://abc.tar.gz
         "#,
         );
     }
 
+    fn read_config_error(string: &'static str) -> String {
+        let cursor = Cursor::new(string.as_bytes());
+        let error = read_config(Box::new(cursor), "root/foo.yaml").expect_err("invalid config");
+        format!("{:#}", error)
+    }
+
+    #[test]
+    fn unknown_requirement() {
+        assert_eq!(
+            read_config_error(
+                r#"
+tools:
+  - name: maven
+    version: 3.6.3
+    requires: [openjkd]
+    download:
+      default: https://maven.tar.gz
+        "#
+            ),
+            "Invalid configuration root/foo.yaml: Tool 'maven' requires unknown tool 'openjkd'"
+        );
+    }
+
+    #[test]
+    fn cyclic_requirements() {
+        assert_eq!(
+            read_config_error(
+                r#"
+tools:
+  - name: a
+    version: 1.0.0
+    requires: [b]
+    download:
+      default: https://a.tar.gz
+  - name: b
+    version: 1.0.0
+    requires: [c]
+    download:
+      default: https://b.tar.gz
+  - name: c
+    version: 1.0.0
+    requires: [b]
+    download:
+      default: https://c.tar.gz
+        "#
+            ),
+            "Invalid configuration root/foo.yaml: Cyclic tool requirements: b -> c -> b"
+        );
+    }
+
     #[test]
     fn test_get_config() {
         let config = get_config(
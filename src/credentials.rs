@@ -0,0 +1,250 @@
+use crate::config::CredentialConfiguration;
+use crate::util::base64_encode;
+use crate::{bail, Result};
+use anyhow::Context;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub hosts: Vec<CredentialConfiguration>,
+    // location of the netrc file used for hosts without credential environment variables
+    pub netrc_path: Option<PathBuf>,
+}
+
+impl Credentials {
+    pub fn new(hosts: Vec<CredentialConfiguration>) -> Credentials {
+        Credentials {
+            hosts,
+            netrc_path: get_netrc_path(),
+        }
+    }
+
+    // Value of the Authorization header for requests to the given host
+    pub fn get_authorization(&self, host: &str) -> Result<Option<String>> {
+        self.get_authorization_with_env(host, &|name| std::env::var(name).ok())
+    }
+
+    // Same as get_authorization, with the environment variables looked up by env
+    fn get_authorization_with_env(
+        &self,
+        host: &str,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Option<String>> {
+        let credential = match self
+            .hosts
+            .iter()
+            .find(|credential| credential.host.eq_ignore_ascii_case(host))
+        {
+            Some(credential) => credential,
+            None => return Ok(None),
+        };
+        if let Some(token_env) = &credential.token_env {
+            let token = get_env(env, token_env, host)?;
+            return Ok(Some(format!("Bearer {}", token)));
+        }
+        if let Some(username_env) = &credential.username_env {
+            let username = get_env(env, username_env, host)?;
+            let password = match &credential.password_env {
+                Some(password_env) => get_env(env, password_env, host)?,
+                None => String::new(),
+            };
+            return Ok(Some(basic_authorization(&username, &password)));
+        }
+        let netrc_path = self
+            .netrc_path
+            .as_ref()
+            .with_context(|| format!("No netrc file to look up credentials for {}", host))?;
+        let content = std::fs::read_to_string(netrc_path)
+            .with_context(|| format!("Unable to read netrc file {:?}", netrc_path))?;
+        match find_netrc_login(&content, host) {
+            Some((login, password)) => Ok(Some(basic_authorization(&login, &password))),
+            None => bail!("No credentials for {} in netrc file {:?}", host, netrc_path),
+        }
+    }
+}
+
+fn get_netrc_path() -> Option<PathBuf> {
+    std::env::var_os("NETRC")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".netrc")))
+}
+
+fn get_env(env: &dyn Fn(&str) -> Option<String>, name: &str, host: &str) -> Result<String> {
+    env(name).with_context(|| {
+        format!(
+            "Environment variable {} with credentials for {} is not set",
+            name, host
+        )
+    })
+}
+
+fn basic_authorization(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64_encode(format!("{}:{}", username, password).as_bytes())
+    )
+}
+
+// Find login and password for the host, falling back to the default entry
+fn find_netrc_login(content: &str, host: &str) -> Option<(String, String)> {
+    let mut tokens = content.split_whitespace();
+    let mut default_login = None;
+    // machine of the current entry, None for the default entry
+    let mut machine: Option<&str> = None;
+    let mut login = None;
+    let mut password = None;
+    let mut finish_entry =
+        |machine: Option<&str>, login: &mut Option<&str>, password: &mut Option<&str>| {
+            let entry = (
+                login.take().unwrap_or_default().to_string(),
+                password.take().unwrap_or_default().to_string(),
+            );
+            match machine {
+                Some(machine) if machine.eq_ignore_ascii_case(host) => Some(entry),
+                Some(_) => None,
+                None => {
+                    default_login.get_or_insert(entry);
+                    None
+                }
+            }
+        };
+    let mut in_entry = false;
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if in_entry {
+                    if let Some(entry) = finish_entry(machine, &mut login, &mut password) {
+                        return Some(entry);
+                    }
+                }
+                in_entry = true;
+                machine = if token == "machine" {
+                    Some(tokens.next().unwrap_or_default())
+                } else {
+                    None
+                };
+            }
+            "login" => login = tokens.next(),
+            "password" => password = tokens.next(),
+            "account" => {
+                tokens.next();
+            }
+            "macdef" => {
+                // macro definitions run until an empty line, which is not supported here
+                break;
+            }
+            _ => {}
+        }
+    }
+    if in_entry {
+        if let Some(entry) = finish_entry(machine, &mut login, &mut password) {
+            return Some(entry);
+        }
+    }
+    default_login
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn create_credentials(netrc: &str) -> (Credentials, tempfile::NamedTempFile) {
+        let mut netrc_file = tempfile::NamedTempFile::new().unwrap();
+        netrc_file.write_all(netrc.as_bytes()).unwrap();
+        let credentials = Credentials {
+            hosts: vec![
+                CredentialConfiguration {
+                    host: "token.example.com".to_string(),
+                    token_env: Some("TOOL_TOOL_TEST_TOKEN".to_string()),
+                    ..Default::default()
+                },
+                CredentialConfiguration {
+                    host: "basic.example.com".to_string(),
+                    username_env: Some("TOOL_TOOL_TEST_USERNAME".to_string()),
+                    password_env: Some("TOOL_TOOL_TEST_PASSWORD".to_string()),
+                    ..Default::default()
+                },
+                CredentialConfiguration {
+                    host: "netrc.example.com".to_string(),
+                    ..Default::default()
+                },
+                CredentialConfiguration {
+                    host: "missing.example.com".to_string(),
+                    token_env: Some("TOOL_TOOL_TEST_MISSING_TOKEN".to_string()),
+                    ..Default::default()
+                },
+            ],
+            netrc_path: Some(netrc_file.path().to_path_buf()),
+        };
+        (credentials, netrc_file)
+    }
+
+    #[test]
+    fn credentials_from_env() {
+        let env = |name: &str| {
+            match name {
+                "TOOL_TOOL_TEST_TOKEN" => Some("secret-token"),
+                "TOOL_TOOL_TEST_USERNAME" => Some("user"),
+                "TOOL_TOOL_TEST_PASSWORD" => Some("secret"),
+                _ => None,
+            }
+            .map(str::to_string)
+        };
+        let (credentials, _netrc_file) = create_credentials("");
+        let get_authorization = |host| credentials.get_authorization_with_env(host, &env);
+        assert_eq!(
+            get_authorization("token.example.com").unwrap(),
+            Some("Bearer secret-token".to_string())
+        );
+        assert_eq!(
+            get_authorization("BASIC.example.com").unwrap(),
+            Some("Basic dXNlcjpzZWNyZXQ=".to_string())
+        );
+        assert_eq!(get_authorization("example.com").unwrap(), None);
+        assert_eq!(
+            get_authorization("missing.example.com")
+                .unwrap_err()
+                .to_string(),
+            "Environment variable TOOL_TOOL_TEST_MISSING_TOKEN with credentials for missing.example.com is not set"
+        );
+    }
+
+    #[test]
+    fn credentials_from_netrc() {
+        let (credentials, _netrc_file) = create_credentials(
+            "machine other.example.com login other password other\n\
+             machine netrc.example.com\n  login user\n  password secret\n",
+        );
+        assert_eq!(
+            credentials.get_authorization("netrc.example.com").unwrap(),
+            Some("Basic dXNlcjpzZWNyZXQ=".to_string())
+        );
+        let (credentials, netrc_file) = create_credentials("machine other.example.com login a");
+        assert_eq!(
+            credentials
+                .get_authorization("netrc.example.com")
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "No credentials for netrc.example.com in netrc file {:?}",
+                netrc_file.path()
+            )
+        );
+    }
+
+    #[test]
+    fn netrc_default_entry() {
+        let netrc =
+            "default login anonymous password guest\nmachine a.example.com login a password b";
+        assert_eq!(
+            find_netrc_login(netrc, "a.example.com"),
+            Some(("a".to_string(), "b".to_string()))
+        );
+        assert_eq!(
+            find_netrc_login(netrc, "b.example.com"),
+            Some(("anonymous".to_string(), "guest".to_string()))
+        );
+        assert_eq!(find_netrc_login("", "a.example.com"), None);
+    }
+}
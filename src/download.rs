@@ -1,33 +1,287 @@
-use crate::util::retry;
+use crate::credentials::Credentials;
+use crate::proxy::{Proxy, ProxySettings};
+use crate::util::{percent_decode, retry};
 use crate::Result;
+use crate::{NAME, VERSION};
 use anyhow::{bail, Context};
-use http_req::response::Response;
-use std::fs::File;
-use std::path::Path;
+use http_req::request::connect_timeout;
+use http_req::response::{find_slice, Response};
+use http_req::tls;
+use http_req::uri::Uri;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const MAX_REDIRECTS: i32 = 10;
+const TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_HEAD_SIZE: usize = 64 * 1024;
 
-pub fn download(url: &str, path: &Path) -> Result<()> {
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+// upper bound for backoff delays and Retry-After headers
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadProgress {
+    pub received: u64,
+    // from the Content-Length header, if sent by the server
+    pub total: Option<u64>,
+    pub bytes_per_second: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadOptions {
+    // number of retries after transient failures
+    pub retries: u32,
+    // delay before the first retry, doubled for each further retry
+    pub retry_delay: Duration,
+    pub proxy: ProxySettings,
+    // looked up for each request, so that credentials are not sent to other hosts on redirects
+    pub credentials: Credentials,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            proxy: ProxySettings::default(),
+            credentials: Credentials::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpError {
+    pub code: u16,
+    pub reason: String,
+    pub url: String,
+    // delay requested by the server via Retry-After
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "HTTP Error {} downloading {} ({})",
+            self.code, self.url, self.reason
+        )
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+// Context for failures that are not fixed by retrying, e.g. local file errors or invalid urls
+#[derive(Debug)]
+struct PermanentError(String);
+
+impl fmt::Display for PermanentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// Download url to path, resuming from the end of an existing partial file at path if possible.
+// Transient failures are retried with exponential backoff.
+pub fn download(
+    url: &str,
+    path: &Path,
+    options: &DownloadOptions,
+    progress: &mut dyn FnMut(&DownloadProgress),
+) -> Result<String> {
+    if let Some(source) = file_url_to_path(url) {
+        copy_local_file(&source, path, progress)?;
+        return Ok(url.to_string());
+    }
+    let mut attempt = 0;
+    // ETag or Last-Modified of the partial download, sent as If-Range when resuming
+    let mut validator = None;
+    loop {
+        let error = match download_once(url, path, options, &mut validator, progress) {
+            Ok(resolved_url) => return Ok(resolved_url),
+            Err(error) => error,
+        };
+        let delay = match get_retry_delay(&error, options, attempt) {
+            Some(delay) if attempt < options.retries => delay,
+            _ if attempt > 0 => {
+                return Err(error.context(format!(
+                    "Failed to download {} after {} attempts",
+                    url,
+                    attempt + 1
+                )))
+            }
+            _ => return Err(error),
+        };
+        attempt += 1;
+        report!(
+            "{:#}, retrying in {:.1}s (attempt {} of {})",
+            error,
+            delay.as_secs_f64(),
+            attempt + 1,
+            options.retries + 1
+        );
+        std::thread::sleep(delay);
+    }
+}
+
+// Local files are copied as a whole, failures are not retried
+fn copy_local_file(
+    source: &Path,
+    path: &Path,
+    progress: &mut dyn FnMut(&DownloadProgress),
+) -> Result<()> {
+    let reader = File::open(source).with_context(|| format!("Unable to open {:?}", source))?;
+    let total = reader.metadata().ok().map(|metadata| metadata.len());
+    let mut file = File::create(path)?;
+    ProgressCounter {
+        offset: 0,
+        total,
+        progress,
+    }
+    .copy(reader, &mut file)
+    .with_context(|| format!("Unable to copy {:?}", source))?;
+    Ok(())
+}
+
+pub fn file_url(path: &Path) -> String {
+    let path = path
+        .to_string_lossy()
+        .replace('\\', "/")
+        .replace('%', "%25")
+        .replace(' ', "%20");
+    if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        // windows paths like C:/tools
+        format!("file:///{}", path)
+    }
+}
+
+fn file_url_to_path(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://")?;
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let path = percent_decode(path);
+    let bytes = path.as_bytes();
+    if bytes.len() > 2 && bytes[0] == b'/' && bytes[2] == b':' {
+        // drop the leading slash of windows paths like /C:/tools
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+// Returns the delay before the next attempt, or None if the error is not transient
+fn get_retry_delay(
+    error: &anyhow::Error,
+    options: &DownloadOptions,
+    attempt: u32,
+) -> Option<Duration> {
+    let backoff = options
+        .retry_delay
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(MAX_RETRY_DELAY);
+    if let Some(http_error) = error.downcast_ref::<HttpError>() {
+        // 501 Not Implemented will not go away by retrying
+        let transient = http_error.code == 429
+            || (http_error.code >= 500 && http_error.code < 600 && http_error.code != 501);
+        if !transient {
+            return None;
+        }
+        let delay = http_error.retry_after.unwrap_or(backoff);
+        return Some(delay.min(MAX_RETRY_DELAY));
+    }
+    if error.downcast_ref::<PermanentError>().is_some() {
+        return None;
+    }
+    // connection errors, timeouts and truncated responses
+    let network_error = error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io_error| is_network_error(io_error.kind()))
+    });
+    if network_error {
+        Some(backoff.min(MAX_RETRY_DELAY))
+    } else {
+        None
+    }
+}
+
+fn is_network_error(kind: std::io::ErrorKind) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(
+        kind,
+        ConnectionRefused
+            | ConnectionReset
+            | ConnectionAborted
+            | NotConnected
+            | BrokenPipe
+            | TimedOut
+            // read timeouts on unix
+            | WouldBlock
+            | UnexpectedEof
+            | Interrupted
+    )
+}
+
+fn download_once(
+    url: &str,
+    path: &Path,
+    options: &DownloadOptions,
+    validator: &mut Option<String>,
+    progress: &mut dyn FnMut(&DownloadProgress),
+) -> Result<String> {
     let mut download_url = url.to_string();
+    // credentials are not sent in clear text after a redirect from https to http
+    let mut send_credentials = true;
     for _ in 0..MAX_REDIRECTS {
-        let mut file = File::create(path)?;
-        let res: Response = http_req::request::get(&download_url, &mut file).unwrap();
+        let offset = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+        let res: Response = request(
+            &download_url,
+            path,
+            offset,
+            options,
+            send_credentials,
+            validator,
+            progress,
+        )?;
         if res.status_code().is_success() {
-            return Ok(());
+            return Ok(download_url);
         }
         if res.status_code().is_redirect() {
-            download_url = res
+            let location = res
                 .headers()
                 .get("location")
-                .expect("No location in HTTP redirect")
+                .with_context(|| format!("No location in HTTP redirect from {}", download_url))?
                 .clone();
+            if is_downgrade(&download_url, &location) {
+                verbose!("Not sending credentials after redirect to {}", location);
+                send_credentials = false;
+            }
+            download_url = location;
             verbose!("Download redirected to {}", download_url);
-            retry(|| std::fs::remove_file(&path))
-                .with_context(|| format!("Unable to remove temp file at {:?}", path))?;
             continue;
         }
         let code: u16 = res.status_code().into();
-        bail!("HTTP Error {} downloading {} ({})", code, url, res.reason())
+        if code == 416 && offset > 0 {
+            verbose!("Unable to resume download of {}, restarting", download_url);
+            retry(|| std::fs::remove_file(path)).with_context(|| {
+                PermanentError(format!("Unable to remove partial download at {:?}", path))
+            })?;
+            continue;
+        }
+        return Err(HttpError {
+            code,
+            reason: res.reason().to_string(),
+            url: url.to_string(),
+            retry_after: res
+                .headers()
+                .get("Retry-After")
+                .and_then(|value| parse_retry_after(value, SystemTime::now())),
+        }
+        .into());
     }
     Err(anyhow::anyhow!(
         "Failed to download {} after {} redirects",
@@ -36,13 +290,423 @@ pub fn download(url: &str, path: &Path) -> Result<()> {
     ))
 }
 
+fn is_downgrade(url: &str, redirect_url: &str) -> bool {
+    let is_https = |url: &str| {
+        url.get(..8)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"))
+    };
+    is_https(url) && !is_https(redirect_url)
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = parse_http_date(value)?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+// Parse an IMF-fixdate like "Sun, 06 Nov 1994 08:49:37 GMT"
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: &[&str] = &[
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+    let day: i64 = parts[1].parse().ok()?;
+    let month = MONTHS.iter().position(|month| *month == parts[2])? as i64 + 1;
+    let year: i64 = parts[3].parse().ok()?;
+    let time: Vec<u64> = parts[4]
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    if time.len() != 3 {
+        return None;
+    }
+    // days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let seconds = u64::try_from(days).ok()? * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+// Send a GET request and write the body of successful responses to the file at path
+fn request(
+    url: &str,
+    path: &Path,
+    offset: u64,
+    options: &DownloadOptions,
+    send_credentials: bool,
+    validator: &mut Option<String>,
+    progress: &mut dyn FnMut(&DownloadProgress),
+) -> Result<Response> {
+    let uri: Uri = url
+        .parse()
+        .with_context(|| PermanentError(format!("Invalid download url {}", url)))?;
+    let host = uri
+        .host()
+        .with_context(|| PermanentError(format!("No host in download url {}", url)))?;
+    let port = uri.corr_port();
+    let host_header = uri.host_header().unwrap_or_else(|| host.to_string());
+    let proxy = options.proxy.get_proxy(uri.scheme(), host, port)?;
+    let (connect_host, connect_port) = match &proxy {
+        Some(proxy) => (proxy.host.as_str(), proxy.port),
+        None => (host, port),
+    };
+    let mut stream = connect_timeout(connect_host, connect_port, TIMEOUT)
+        .with_context(|| format!("Unable to connect to {}:{}", connect_host, connect_port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut headers = vec![];
+    if offset > 0 {
+        headers.push(("Range", format!("bytes={}-", offset)));
+        // the server sends the whole file instead if it changed since the partial download
+        if let Some(validator) = validator.as_ref() {
+            headers.push(("If-Range", validator.clone()));
+        }
+    }
+    if send_credentials {
+        if let Some(authorization) = options.credentials.get_authorization(host)? {
+            verbose!("Using credentials for {}", host);
+            headers.push(("Authorization", authorization));
+        }
+    }
+    if uri.scheme() == "https" {
+        if let Some(proxy) = &proxy {
+            connect_tunnel(&mut stream, &host_header, host, port, proxy)?;
+        }
+        let mut stream = tls::Config::default()
+            .connect(host, stream)
+            .with_context(|| format!("Unable to establish TLS connection to {}", host))?;
+        let message = format_request(uri.resource(), &host_header, &headers);
+        send(&mut stream, &message, path, offset, validator, progress)
+    } else {
+        let target = match &proxy {
+            Some(proxy) => {
+                // plain http requests are forwarded by the proxy based on the absolute url
+                if let Some(authorization) = &proxy.authorization {
+                    headers.push(("Proxy-Authorization", authorization.clone()));
+                }
+                url.split('#').next().unwrap_or(url)
+            }
+            None => uri.resource(),
+        };
+        let message = format_request(target, &host_header, &headers);
+        send(&mut stream, &message, path, offset, validator, progress)
+    }
+}
+
+// Ask the proxy to open a tunnel to the target host for https requests
+fn connect_tunnel<S: Read + Write>(
+    stream: &mut S,
+    host_header: &str,
+    host: &str,
+    port: u16,
+    proxy: &Proxy,
+) -> Result<()> {
+    let mut message = format!(
+        "CONNECT {}:{} HTTP/1.1\r\nHost: {}\r\n",
+        host, port, host_header
+    );
+    if let Some(authorization) = &proxy.authorization {
+        message += &format!("Proxy-Authorization: {}\r\n", authorization);
+    }
+    message += "\r\n";
+    stream.write_all(message.as_bytes())?;
+    stream.flush()?;
+    let (response, _) = read_head(stream)?;
+    if !response.status_code().is_success() {
+        let code: u16 = response.status_code().into();
+        bail!(
+            "Proxy {}:{} refused to connect to {}:{} (HTTP {} {})",
+            proxy.host,
+            proxy.port,
+            host,
+            port,
+            code,
+            response.reason()
+        );
+    }
+    Ok(())
+}
+
+// Read the status line and headers, returns the response and the start of the body
+fn read_head<S: Read>(stream: &mut S) -> Result<(Response, Vec<u8>)> {
+    let mut buffer = vec![];
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(head_end) = find_slice(&buffer, b"\r\n\r\n") {
+            let body_part = buffer.split_off(head_end);
+            return Ok((Response::from_head(&buffer)?, body_part));
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            bail!("HTTP response head exceeds {} bytes", MAX_HEAD_SIZE);
+        }
+        let count = stream.read(&mut chunk)?;
+        if count == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Connection closed before receiving a response",
+            )
+            .into());
+        }
+        buffer.extend_from_slice(&chunk[..count]);
+    }
+}
+
+fn format_request(target: &str, host_header: &str, headers: &[(&str, String)]) -> String {
+    let mut message = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}/{}\r\nConnection: Close\r\n",
+        target, host_header, NAME, VERSION
+    );
+    for (name, value) in headers {
+        message += &format!("{}: {}\r\n", name, value);
+    }
+    message += "\r\n";
+    message
+}
+
+fn send<S: Read + Write>(
+    stream: &mut S,
+    message: &str,
+    path: &Path,
+    offset: u64,
+    validator: &mut Option<String>,
+    progress: &mut dyn FnMut(&DownloadProgress),
+) -> Result<Response> {
+    stream.write_all(message.as_bytes())?;
+    stream.flush()?;
+    let (response, body_part) = read_head(stream)?;
+    if !response.status_code().is_success() {
+        return Ok(response);
+    }
+    *validator = get_validator(&response);
+    let partial_content = u16::from(response.status_code()) == 206;
+    let (mut file, offset) = if partial_content {
+        let range_start = response
+            .headers()
+            .get("Content-Range")
+            .and_then(|range| parse_content_range_start(range));
+        if offset == 0 || range_start != Some(offset) {
+            retry(|| std::fs::remove_file(path)).with_context(|| {
+                PermanentError(format!("Unable to remove partial download at {:?}", path))
+            })?;
+            bail!(
+                "Unexpected partial content range {:?} when resuming at byte {}",
+                response.headers().get("Content-Range"),
+                offset
+            );
+        }
+        verbose!("Resuming download at byte {}", offset);
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                PermanentError(format!("Unable to open partial download at {:?}", path))
+            })?;
+        (file, offset)
+    } else {
+        // the server does not support ranges, so start over
+        let file = File::create(path)
+            .with_context(|| PermanentError(format!("Unable to create {:?}", path)))?;
+        (file, 0)
+    };
+    let content_length = response.content_len().map(|len| len as u64);
+    let body = body_part.as_slice().chain(stream);
+    let chunked = response
+        .headers()
+        .get("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+    let mut counter = ProgressCounter {
+        offset,
+        total: content_length.map(|length| length + offset),
+        progress,
+    };
+    let received = if chunked {
+        counter.total = None;
+        counter.copy(ChunkedReader::new(BufReader::new(body)), &mut file)?
+    } else if let Some(content_length) = content_length {
+        counter.copy(body.take(content_length), &mut file)?
+    } else {
+        counter.copy(body, &mut file)?
+    };
+    if let Some(content_length) = content_length {
+        if !chunked && received < content_length {
+            // an io error, so the download is retried and resumed
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Connection closed after receiving {} of {} bytes",
+                    received, content_length
+                ),
+            )
+            .into());
+        }
+    }
+    Ok(response)
+}
+
+// Weak ETags cannot be used with If-Range, Last-Modified is used instead
+fn get_validator(response: &Response) -> Option<String> {
+    let headers = response.headers();
+    headers
+        .get("ETag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| headers.get("Last-Modified"))
+        .cloned()
+}
+
+// Parse the start of a "Content-Range: bytes <start>-<end>/<size>" header
+fn parse_content_range_start(content_range: &str) -> Option<u64> {
+    content_range
+        .trim()
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+struct ProgressCounter<'a> {
+    // bytes already downloaded before this request
+    offset: u64,
+    total: Option<u64>,
+    progress: &'a mut dyn FnMut(&DownloadProgress),
+}
+
+impl ProgressCounter<'_> {
+    // Copy the body to the writer, returns the number of bytes received in this request
+    fn copy<R: Read>(&mut self, mut reader: R, writer: &mut dyn Write) -> Result<u64> {
+        let start = Instant::now();
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut received = 0u64;
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            writer
+                .write_all(&buffer[..count])
+                .with_context(|| PermanentError("Unable to write download".to_string()))?;
+            received += count as u64;
+            let elapsed = start.elapsed().as_secs_f64();
+            (self.progress)(&DownloadProgress {
+                received: self.offset + received,
+                total: self.total,
+                bytes_per_second: if elapsed > 0.0 {
+                    received as f64 / elapsed
+                } else {
+                    0.0
+                },
+            });
+        }
+        writer
+            .flush()
+            .with_context(|| PermanentError("Unable to write download".to_string()))?;
+        Ok(received)
+    }
+}
+
+// Decodes a body sent with "Transfer-Encoding: chunked", trailers are ignored
+struct ChunkedReader<R> {
+    reader: R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(reader: R) -> Self {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            done: false,
+        }
+    }
+}
+
+fn invalid_chunk(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let size = line.split(';').next().unwrap_or_default().trim();
+            self.remaining =
+                u64::from_str_radix(size, 16).map_err(|_| invalid_chunk("invalid chunk size"))?;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let max = buf.len().min(self.remaining as usize);
+        let count = self.reader.read(&mut buf[..max])?;
+        if count == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= count as u64;
+        if self.remaining == 0 {
+            let mut line_end = [0u8; 2];
+            self.reader.read_exact(&mut line_end)?;
+            if &line_end != b"\r\n" {
+                return Err(invalid_chunk("missing line end after chunk"));
+            }
+        }
+        Ok(count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use mockito::mock;
+    use mockito::{mock, Matcher};
 
     use super::*;
+    use crate::config::CredentialConfiguration;
     use std::fs::read_to_string;
 
+    const NO_RETRIES: DownloadOptions = DownloadOptions {
+        retries: 0,
+        retry_delay: Duration::from_millis(0),
+        proxy: ProxySettings {
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: vec![],
+        },
+        credentials: Credentials {
+            hosts: vec![],
+            netrc_path: None,
+        },
+    };
+    const FAST_RETRIES: DownloadOptions = DownloadOptions {
+        retries: 2,
+        retry_delay: Duration::from_millis(1),
+        proxy: ProxySettings {
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: vec![],
+        },
+        credentials: Credentials {
+            hosts: vec![],
+            netrc_path: None,
+        },
+    };
+
     #[test]
     fn simple_download() {
         let path = "/download1";
@@ -52,7 +716,13 @@ mod tests {
             .create();
 
         let file = create_temp_path();
-        download(&(mockito::server_url() + path), &file).unwrap();
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |_| {},
+        )
+        .unwrap();
         let result = read_to_string(&file).unwrap();
         assert_eq!("world", result);
     }
@@ -71,11 +741,639 @@ mod tests {
             .create();
 
         let file = create_temp_path();
-        download(&(mockito::server_url() + redirect_path), &file).unwrap();
+        let resolved_url = download(
+            &(mockito::server_url() + redirect_path),
+            &file,
+            &NO_RETRIES,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(resolved_url, mockito::server_url() + path);
         let result = read_to_string(&file).unwrap();
         assert_eq!(result, "world");
     }
 
+    #[test]
+    fn download_with_progress() {
+        let path = "/download5";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body("Hello, World!")
+            .create();
+
+        let file = create_temp_path();
+        let mut updates = vec![];
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |progress| updates.push((progress.received, progress.total)),
+        )
+        .unwrap();
+        assert_eq!(updates.last(), Some(&(13, Some(13))));
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn chunked_download() {
+        let path = "/download6";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body_from_fn(|writer| {
+                writer.write_all(b"Hello, ")?;
+                writer.write_all(b"World!")
+            })
+            .create();
+
+        let file = create_temp_path();
+        let mut updates = vec![];
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |progress| updates.push((progress.received, progress.total)),
+        )
+        .unwrap();
+        assert_eq!(updates.last(), Some(&(13, None)));
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn resume_download() {
+        let path = "/download7";
+        let _m = mock("GET", path)
+            .match_header("range", "bytes=7-")
+            .with_status(206)
+            .with_header("content-range", "bytes 7-12/13")
+            .with_body("World!")
+            .create();
+
+        let file = create_temp_path();
+        std::fs::write(&file, "Hello, ").unwrap();
+        let mut updates = vec![];
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |progress| updates.push((progress.received, progress.total)),
+        )
+        .unwrap();
+        assert_eq!(updates.last(), Some(&(13, Some(13))));
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn resume_download_without_range_support() {
+        let path = "/download8";
+        let _m = mock("GET", path)
+            .with_status(200)
+            .with_body("Hello, World!")
+            .create();
+
+        let file = create_temp_path();
+        std::fs::write(&file, "Hello, ").unwrap();
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn resume_download_with_unsatisfiable_range() {
+        let path = "/download9";
+        let _m = mock("GET", path)
+            .match_header("range", "bytes=23-")
+            .with_status(416)
+            .create();
+        let _m2 = mock("GET", path)
+            .match_header("range", Matcher::Missing)
+            .with_status(200)
+            .with_body("Hello, World!")
+            .create();
+
+        let file = create_temp_path();
+        std::fs::write(&file, "Hello, Wonderful World!").unwrap();
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn resume_download_with_unexpected_range() {
+        let path = "/download10";
+        let _m = mock("GET", path)
+            .with_status(206)
+            .with_header("content-range", "bytes 0-12/13")
+            .with_body("Hello, World!")
+            .create();
+
+        let file = create_temp_path();
+        std::fs::write(&file, "Hello, ").unwrap();
+        let error = download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |_| {},
+        )
+        .expect_err("unexpected range");
+        assert_eq!(
+            error.to_string(),
+            "Unexpected partial content range Some(\"bytes 0-12/13\") when resuming at byte 7"
+        );
+        assert!(!file.exists(), "partial download should be removed");
+    }
+
+    #[test]
+    fn content_range_start() {
+        assert_eq!(parse_content_range_start("bytes 7-12/13"), Some(7));
+        assert_eq!(parse_content_range_start("bytes 7-12/*"), Some(7));
+        assert_eq!(parse_content_range_start("bytes */13"), None);
+        assert_eq!(parse_content_range_start("items 7-12/13"), None);
+    }
+
+    #[test]
+    fn retry_server_error() {
+        let path = "/download11";
+        let _m = mock("GET", path).with_status(503).create();
+        let _m2 = mock("GET", path)
+            .with_status(200)
+            .with_body("Hello, World!")
+            .create();
+
+        let file = create_temp_path();
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &FAST_RETRIES,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn retry_too_many_requests() {
+        let path = "/download12";
+        let _m = mock("GET", path)
+            .with_status(429)
+            .with_header("retry-after", "0")
+            .create();
+        let _m2 = mock("GET", path)
+            .with_status(200)
+            .with_body("Hello, World!")
+            .create();
+
+        let file = create_temp_path();
+        download(
+            &(mockito::server_url() + path),
+            &file,
+            &FAST_RETRIES,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn retry_until_giving_up() {
+        let path = "/download13";
+        let m = mock("GET", path).with_status(503).expect(3).create();
+
+        let file = create_temp_path();
+        let result = download(
+            &(mockito::server_url() + path),
+            &file,
+            &FAST_RETRIES,
+            &mut |_| {},
+        );
+        let error = result.expect_err("download should fail");
+        m.assert();
+        assert_eq!(
+            format!("{:#}", error).replace(&mockito::server_address().port().to_string(), "9999"),
+            "Failed to download http://127.0.0.1:9999/download13 after 3 attempts: HTTP Error 503 downloading http://127.0.0.1:9999/download13 (Service Unavailable)"
+        );
+    }
+
+    #[test]
+    fn do_not_retry_client_error() {
+        let path = "/download14";
+        let m = mock("GET", path).with_status(404).expect(1).create();
+
+        let file = create_temp_path();
+        let result = download(
+            &(mockito::server_url() + path),
+            &file,
+            &FAST_RETRIES,
+            &mut |_| {},
+        );
+        m.assert();
+        assert_eq!(
+            get_error_message(result),
+            "HTTP Error 404 downloading http://127.0.0.1:9999/download14 (Not Found)"
+        );
+    }
+
+    #[test]
+    fn retry_connection_error() {
+        let file = create_temp_path();
+        let error = download("http://127.0.0.1:1/tool", &file, &FAST_RETRIES, &mut |_| {})
+            .expect_err("connection should fail");
+        assert_eq!(
+            error.to_string(),
+            "Failed to download http://127.0.0.1:1/tool after 3 attempts"
+        );
+    }
+
+    #[test]
+    fn do_not_retry_local_file_error() {
+        let path = "/download15";
+        let m = mock("GET", path)
+            .with_status(200)
+            .with_body("world")
+            .expect(1)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("missing/download");
+        let result = download(
+            &(mockito::server_url() + path),
+            &file,
+            &FAST_RETRIES,
+            &mut |_| {},
+        );
+        m.assert();
+        assert!(get_error_message(result).starts_with("Unable to create"));
+    }
+
+    #[test]
+    fn do_not_retry_invalid_url() {
+        let file = create_temp_path();
+        let error = download(
+            "http://example.com:port/tool",
+            &file,
+            &FAST_RETRIES,
+            &mut |_| {},
+        )
+        .expect_err("url should be invalid");
+        assert_eq!(
+            error.to_string(),
+            "Invalid download url http://example.com:port/tool"
+        );
+    }
+
+    // Serve the responses to consecutive connections, the join handle returns the request heads
+    fn serve_responses(
+        responses: Vec<&'static str>,
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tool", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            responses
+                .iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut head = vec![];
+                    let mut chunk = [0u8; 1024];
+                    while find_slice(&head, b"\r\n\r\n").is_none() {
+                        let count = stream.read(&mut chunk).unwrap();
+                        head.extend_from_slice(&chunk[..count]);
+                    }
+                    stream.write_all(response.as_bytes()).unwrap();
+                    String::from_utf8(head).unwrap()
+                })
+                .collect()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn resume_truncated_download() {
+        let (url, server) = serve_responses(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nETag: \"v1\"\r\n\r\nHello, ",
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: 6\r\nContent-Range: bytes 7-12/13\r\nETag: \"v1\"\r\n\r\nWorld!",
+        ]);
+        let file = create_temp_path();
+        download(&url, &file, &FAST_RETRIES, &mut |_| {}).unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, World!");
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("Range"), "{}", requests[0]);
+        assert!(
+            requests[1].contains("\r\nRange: bytes=7-\r\nIf-Range: \"v1\"\r\n"),
+            "{}",
+            requests[1]
+        );
+    }
+
+    #[test]
+    fn restart_download_of_changed_file() {
+        let (url, server) = serve_responses(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nLast-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\nHello, ",
+            "HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nHello, Moon!",
+        ]);
+        let file = create_temp_path();
+        download(&url, &file, &FAST_RETRIES, &mut |_| {}).unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "Hello, Moon!");
+        let requests = server.join().unwrap();
+        assert!(
+            requests[1].contains("\r\nIf-Range: Sun, 06 Nov 1994 08:49:37 GMT\r\n"),
+            "{}",
+            requests[1]
+        );
+    }
+
+    #[test]
+    fn retry_after() {
+        let now = UNIX_EPOCH + Duration::from_secs(784_111_000);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::from_secs(777))
+        );
+        assert_eq!(
+            parse_retry_after(
+                "Sun, 06 Nov 1994 08:49:37 GMT",
+                now + Duration::from_secs(1000)
+            ),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn http_date() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        );
+        assert_eq!(
+            parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"),
+            Some(UNIX_EPOCH)
+        );
+        assert_eq!(
+            parse_http_date("Tue, 29 Feb 2000 12:00:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(951_825_600))
+        );
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn chunked_reader() {
+        let body = b"7\r\nHello, \r\n6;ext=1\r\nWorld!\r\n0\r\nTrailer: x\r\n\r\n";
+        let mut result = String::new();
+        ChunkedReader::new(&body[..])
+            .read_to_string(&mut result)
+            .unwrap();
+        assert_eq!(result, "Hello, World!");
+    }
+
+    #[test]
+    fn chunked_reader_truncated() {
+        let body = b"7\r\nHello";
+        let mut result = String::new();
+        let error = ChunkedReader::new(&body[..])
+            .read_to_string(&mut result)
+            .expect_err("truncated body");
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn download_with_credentials() {
+        let _m = mock("GET", "/private")
+            .match_header("authorization", "Basic dXNlcjpzZWNyZXQ=")
+            .with_status(302)
+            .with_header(
+                "Location",
+                &format!(
+                    "http://localhost:{}/redirected",
+                    mockito::server_address().port()
+                ),
+            )
+            .create();
+        let _m2 = mock("GET", "/redirected")
+            .match_header("authorization", Matcher::Missing)
+            .with_status(200)
+            .with_body("private")
+            .create();
+
+        let host = mockito::server_address().ip().to_string();
+        let mut netrc_file = tempfile::NamedTempFile::new().unwrap();
+        write!(netrc_file, "machine {} login user password secret", host).unwrap();
+        let options = DownloadOptions {
+            credentials: Credentials {
+                hosts: vec![CredentialConfiguration {
+                    host,
+                    ..Default::default()
+                }],
+                netrc_path: Some(netrc_file.path().to_path_buf()),
+            },
+            ..NO_RETRIES
+        };
+        let file = create_temp_path();
+        download(
+            &(mockito::server_url() + "/private"),
+            &file,
+            &options,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "private");
+    }
+
+    #[test]
+    fn redirect_downgrade() {
+        assert!(is_downgrade(
+            "https://example.com/tool",
+            "http://example.com/tool"
+        ));
+        assert!(is_downgrade(
+            "HTTPS://example.com/tool",
+            "HTTP://example.com:443/tool"
+        ));
+        assert!(!is_downgrade(
+            "https://example.com/tool",
+            "https://cdn.example.com/tool"
+        ));
+        assert!(!is_downgrade(
+            "http://example.com/tool",
+            "https://example.com/tool"
+        ));
+        assert!(!is_downgrade(
+            "http://example.com/tool",
+            "http://example.com:8080/tool"
+        ));
+    }
+
+    #[test]
+    fn download_local_file() {
+        let source = tempfile::tempdir().unwrap();
+        let source_path = source.path().join("local tool");
+        std::fs::write(&source_path, "local").unwrap();
+        let url = file_url(&source_path);
+        assert!(url.starts_with("file:///"), "{}", url);
+        assert!(url.ends_with("/local%20tool"), "{}", url);
+        let file = create_temp_path();
+        let mut received = 0;
+        let resolved_url = download(&url, &file, &FAST_RETRIES, &mut |status| {
+            received = status.received
+        })
+        .unwrap();
+        assert_eq!(resolved_url, url);
+        assert_eq!(read_to_string(&file).unwrap(), "local");
+        assert_eq!(received, 5);
+
+        let error = download(
+            &file_url(&source.path().join("missing")),
+            &file,
+            &FAST_RETRIES,
+            &mut |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Unable to open {:?}", source.path().join("missing"))
+        );
+    }
+
+    #[test]
+    fn file_urls() {
+        assert_eq!(
+            file_url_to_path("file:///opt/tools/a%20b.tar.gz"),
+            Some(PathBuf::from("/opt/tools/a b.tar.gz"))
+        );
+        assert_eq!(
+            file_url_to_path("file://localhost/opt/tool"),
+            Some(PathBuf::from("/opt/tool"))
+        );
+        assert_eq!(
+            file_url_to_path("file:///C:/tools/tool.zip"),
+            Some(PathBuf::from("C:/tools/tool.zip"))
+        );
+        assert_eq!(file_url_to_path("https://example.com/tool"), None);
+        assert_eq!(
+            file_url(Path::new("C:\\tools\\tool.zip")),
+            "file:///C:/tools/tool.zip"
+        );
+    }
+
+    #[test]
+    fn download_via_http_proxy() {
+        let _m = mock("GET", "http://example.com/proxied")
+            .match_header("host", "example.com")
+            .match_header("proxy-authorization", "Basic dXNlcjpzZWNyZXQ=")
+            .with_status(200)
+            .with_body("proxied")
+            .create();
+
+        let options = DownloadOptions {
+            proxy: ProxySettings {
+                http_proxy: Some(format!("http://user:secret@{}", mockito::server_address())),
+                https_proxy: None,
+                no_proxy: vec![],
+            },
+            ..NO_RETRIES
+        };
+        let file = create_temp_path();
+        download("http://example.com/proxied", &file, &options, &mut |_| {}).unwrap();
+        assert_eq!(read_to_string(&file).unwrap(), "proxied");
+    }
+
+    // Stream replaying a canned response and recording everything written to it
+    struct FakeStream {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl FakeStream {
+        fn new(response: &str) -> Self {
+            FakeStream {
+                input: std::io::Cursor::new(response.as_bytes().to_vec()),
+                output: vec![],
+            }
+        }
+    }
+
+    impl Read for FakeStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn create_proxy() -> Proxy {
+        Proxy {
+            host: "proxy".to_string(),
+            port: 3128,
+            authorization: Some("Basic dXNlcjpzZWNyZXQ=".to_string()),
+        }
+    }
+
+    #[test]
+    fn connect_tunnel_established() {
+        let mut stream = FakeStream::new("HTTP/1.1 200 Connection established\r\n\r\n");
+        connect_tunnel(
+            &mut stream,
+            "example.com",
+            "example.com",
+            443,
+            &create_proxy(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(stream.output).unwrap(),
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com\r\nProxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn connect_tunnel_refused() {
+        let mut stream = FakeStream::new(
+            "HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n",
+        );
+        let error = connect_tunnel(
+            &mut stream,
+            "example.com",
+            "example.com",
+            443,
+            &create_proxy(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Proxy proxy:3128 refused to connect to example.com:443 (HTTP 407 Proxy Authentication Required)"
+        );
+    }
+
+    #[test]
+    fn read_head_without_body() {
+        let error = read_head(&mut FakeStream::new("HTTP/1.1 200 OK\r\n")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Connection closed before receiving a response"
+        );
+    }
+
     fn create_temp_path() -> tempfile::TempPath {
         tempfile::NamedTempFile::new().unwrap().into_temp_path()
     }
@@ -86,7 +1384,12 @@ mod tests {
         let _m = mock("GET", path).with_status(500).create();
 
         let file = create_temp_path();
-        let result = download(&(mockito::server_url() + path), &file);
+        let result = download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |_| {},
+        );
         let error_message = get_error_message(result);
         assert_eq!(
             error_message,
@@ -97,7 +1400,7 @@ mod tests {
         );
     }
 
-    fn get_error_message(result: Result<()>) -> String {
+    fn get_error_message(result: Result<String>) -> String {
         let error_message = result.unwrap_err().to_string();
         error_message.replace(&format!("{}", mockito::server_address().port()), "9999")
     }
@@ -111,7 +1414,12 @@ mod tests {
             .create();
 
         let file = create_temp_path();
-        let result = download(&(mockito::server_url() + path), &file);
+        let result = download(
+            &(mockito::server_url() + path),
+            &file,
+            &NO_RETRIES,
+            &mut |_| {},
+        );
         let error_message = get_error_message(result);
         assert_eq!(
             error_message,
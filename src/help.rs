@@ -1,4 +1,5 @@
 use crate::config::{Configuration, CONFIG_FILENAME};
+use crate::lockfile::LOCK_FILENAME;
 use crate::{Result, HOMEPAGE};
 use crate::{NAME, VERSION};
 use std::io::Write;
@@ -9,7 +10,13 @@ struct Command {
     // TODO: description
 }
 
-pub fn print_help(configuration: &Configuration, out: &mut dyn Write) -> Result<()> {
+// Print the help, the configuration is an error if the tool-tool file could not be read
+pub fn print_help(configuration: &Result<Configuration>, out: &mut dyn Write) -> Result<()> {
+    let default_configuration = Configuration::default();
+    let (configuration, error) = match configuration {
+        Ok(configuration) => (configuration, None),
+        Err(error) => (&default_configuration, Some(error)),
+    };
     writeln!(out, "🔧 {} {} 🔧", NAME, VERSION)?;
     writeln!(out)?;
     writeln!(out, "🔧 A light-weight meta-tool to version and install tool dependencies for your software projects")?;
@@ -18,14 +25,67 @@ pub fn print_help(configuration: &Configuration, out: &mut dyn Write) -> Result<
         writeln!(out, "🔧 Loaded configuration from {}", configuration_file)?;
     }
     writeln!(out)?;
-    writeln!(out, "Usage: tt [-v] <command> <args...>")?;
+    writeln!(
+        out,
+        "Usage: tt [-v] [--frozen] [--offline] <command> <args...>"
+    )?;
     writeln!(out, "  Run tool <command> with the provided arguments")?;
     writeln!(out)?;
     writeln!(out, "Flags:")?;
-    writeln!(out, "  -v     Verbose debug output")?;
+    writeln!(out, "  -v        Verbose debug output")?;
+    writeln!(
+        out,
+        "  --frozen  Refuse to download tools not matching the lockfile"
+    )?;
+    writeln!(
+        out,
+        "  --offline Never download tools, fail if any are missing (or set TOOL_TOOL_OFFLINE)"
+    )?;
     writeln!(out)?;
-    writeln!(out, "Usage: tt --download")?;
-    writeln!(out, "  Download configured tools for later use")?;
+    writeln!(
+        out,
+        "Usage: tt --download [--frozen] [--offline] [<tool|tag>...]"
+    )?;
+    writeln!(
+        out,
+        "  Download configured tools for later use, or only the given tools and tags"
+    )?;
+    writeln!(out)?;
+    writeln!(out, "Usage: tt --lock [--offline]")?;
+    writeln!(
+        out,
+        "  Download configured tools for all platforms and record their checksums in {}",
+        LOCK_FILENAME
+    )?;
+    writeln!(out)?;
+    writeln!(out, "Usage: tt --verify [--offline]")?;
+    writeln!(
+        out,
+        "  Check installed tools for missing or modified files and reinstall damaged ones"
+    )?;
+    writeln!(out)?;
+    writeln!(out, "Usage: tt --check")?;
+    writeln!(
+        out,
+        "  Check the configuration for unknown templates, tools and commands without running anything"
+    )?;
+    writeln!(out)?;
+    writeln!(out, "Usage: tt --reinstall [--offline] <tool...>|--all")?;
+    writeln!(out, "  Remove the given tools and download them again")?;
+    writeln!(out)?;
+    writeln!(out, "Usage: tt --clean [--all] [--shared]")?;
+    writeln!(
+        out,
+        "  Remove tool versions no longer in the configuration and leftover temporary files"
+    )?;
+    writeln!(
+        out,
+        "  --all     Remove all downloaded tools of this project"
+    )?;
+    writeln!(
+        out,
+        "  --shared  Remove the cache shared between all projects as well"
+    )?;
     writeln!(out)?;
     writeln!(out, "Usage: tt --getBinaryPath <command>")?;
     writeln!(out, "  Writes the absolute path to the binary to stdout. This can be used for integration with other tooling.")?;
@@ -33,11 +93,19 @@ pub fn print_help(configuration: &Configuration, out: &mut dyn Write) -> Result<
     writeln!(out, "Usage: tt --getToolPath <tool>")?;
     writeln!(out, "  Writes the absolute path to the tool directory to stdout. This can be used for integration with other tooling.")?;
     writeln!(out)?;
-    if configuration.configuration_files.is_empty() {
+    if let Some(error) = error {
+        writeln!(
+            out,
+            "Could not read the tool-tool configuration: {:#}",
+            error
+        )?;
+        writeln!(out, "Refer to {} for further information", HOMEPAGE)?;
+    } else if configuration.configuration_files.is_empty() {
         writeln!(out, "No tool-tool file named {} found in current directory or ancestors, please create one and configure your tools.", CONFIG_FILENAME)?;
         writeln!(out, "Refer to {} for further information", HOMEPAGE)?;
     } else {
         print_commands(out, &configuration)?;
+        print_requirements(out, configuration)?;
     }
     Ok(())
 }
@@ -79,15 +147,41 @@ fn print_commands(out: &mut dyn Write, configuration: &Configuration) -> Result<
     Ok(())
 }
 
+fn print_requirements(out: &mut dyn Write, configuration: &Configuration) -> Result<()> {
+    let mut tools: Vec<_> = configuration
+        .tools
+        .iter()
+        .filter(|tool| !tool.requires.is_empty())
+        .collect();
+    if tools.is_empty() {
+        return Ok(());
+    }
+    tools.sort_by_key(|tool| tool.name.clone());
+    writeln!(out)?;
+    writeln!(out, "Tool requirements:")?;
+    writeln!(out)?;
+    for tool in tools {
+        writeln!(
+            out,
+            "   {} {} -> {}",
+            tool.name,
+            tool.version,
+            tool.requires.join(", ")
+        )?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::ToolConfiguration;
+    use anyhow::Context;
     use std::io::Cursor;
 
-    fn assert_help(configuration: &Configuration) {
+    fn assert_help(configuration: Result<Configuration>) {
         let mut buffer = Cursor::new(vec![]);
-        print_help(configuration, &mut buffer).unwrap();
+        print_help(&configuration, &mut buffer).unwrap();
         let help_text = String::from_utf8(buffer.into_inner()).unwrap();
         let help_text = help_text.replace(
             &format!("🔧 {} {} 🔧", NAME, VERSION),
@@ -98,7 +192,7 @@ mod tests {
 
     #[test]
     fn help_empty() {
-        assert_help(&Configuration::default());
+        assert_help(Ok(Configuration::default()));
     }
 
     #[test]
@@ -126,8 +220,17 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
+            requires: vec!["foo".to_string()],
             ..ToolConfiguration::default()
         });
-        assert_help(&configuration);
+        assert_help(Ok(configuration));
+    }
+
+    #[test]
+    fn help_invalid_configuration() {
+        assert_help(
+            Err(anyhow::anyhow!("Tool 'fizz' requires unknown tool 'foo'"))
+                .context("Invalid configuration foo.bar.yaml"),
+        );
     }
 }
@@ -0,0 +1,131 @@
+use crate::config::Configuration;
+use crate::Result;
+use anyhow::Context;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const LOCK_FILENAME: &str = ".tool-tool.v1.lock";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub tools: Vec<LockedTool>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedTool {
+    pub name: String,
+    pub version: String,
+    // locked artifacts by platform name (default, linux, windows)
+    pub artifacts: BTreeMap<String, LockedArtifact>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedArtifact {
+    pub url: String,
+    pub resolved_url: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Lockfile {
+    pub fn get_artifact(&self, name: &str, version: &str, url: &str) -> Option<&LockedArtifact> {
+        self.tools
+            .iter()
+            .filter(|tool| tool.name == name && tool.version == version)
+            .flat_map(|tool| tool.artifacts.values())
+            .find(|artifact| artifact.url == url)
+    }
+}
+
+pub fn get_lockfile_path(configuration: &Configuration) -> Option<PathBuf> {
+    configuration
+        .configuration_files
+        .first()
+        .and_then(|configuration_file| Path::new(configuration_file).parent())
+        .map(|parent| parent.join(LOCK_FILENAME))
+}
+
+pub fn read_lockfile(path: &Path) -> Result<Lockfile> {
+    let file = File::open(path).with_context(|| format!("Unable to open lockfile {:?}", path))?;
+    serde_yaml::from_reader(file).with_context(|| format!("Unable to parse lockfile {:?}", path))
+}
+
+pub fn write_lockfile(path: &Path, lockfile: &Lockfile) -> Result<()> {
+    AtomicFile::new(path, AllowOverwrite)
+        .write(|file| {
+            writeln!(
+                file,
+                "# tool-tool lockfile - generated by 'tt --lock', do not edit manually"
+            )?;
+            serde_yaml::to_writer(file, lockfile).map_err(std::io::Error::other)
+        })
+        .with_context(|| format!("Unable to write lockfile {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_lockfile() -> Lockfile {
+        let mut artifacts = BTreeMap::new();
+        artifacts.insert(
+            "linux".to_string(),
+            LockedArtifact {
+                url: "https://example.com/foo-linux.tar.gz".to_string(),
+                resolved_url: "https://cdn.example.com/foo-linux.tar.gz".to_string(),
+                size: 13,
+                sha256: "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+                    .to_string(),
+            },
+        );
+        Lockfile {
+            tools: vec![LockedTool {
+                name: "foo".to_string(),
+                version: "1.2.3".to_string(),
+                artifacts,
+            }],
+        }
+    }
+
+    #[test]
+    fn get_artifact() {
+        let lockfile = create_lockfile();
+        let url = "https://example.com/foo-linux.tar.gz";
+        assert_eq!(
+            lockfile.get_artifact("foo", "1.2.3", url),
+            lockfile.tools[0].artifacts.get("linux")
+        );
+        assert_eq!(lockfile.get_artifact("foo", "1.2.4", url), None);
+        assert_eq!(lockfile.get_artifact("bar", "1.2.3", url), None);
+        assert_eq!(
+            lockfile.get_artifact("foo", "1.2.3", "https://example.com/foo.zip"),
+            None
+        );
+    }
+
+    #[test]
+    fn lockfile_path() {
+        let mut configuration = Configuration::default();
+        assert_eq!(get_lockfile_path(&configuration), None);
+        configuration
+            .configuration_files
+            .push("root/.tool-tool.v1.yaml".to_string());
+        assert_eq!(
+            get_lockfile_path(&configuration),
+            Some(PathBuf::from("root").join(LOCK_FILENAME))
+        );
+    }
+
+    #[test]
+    fn write_and_read_lockfile() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(LOCK_FILENAME);
+        let lockfile = create_lockfile();
+        write_lockfile(&path, &lockfile).unwrap();
+        assert_eq!(read_lockfile(&path).unwrap(), lockfile);
+    }
+}
@@ -15,21 +15,34 @@ macro_rules! report {
         eprintln!($($arg)+);
     });
 }
+pub mod archive;
 pub mod args;
 pub mod cache;
+pub mod check;
+pub mod checksum;
 pub mod config;
+pub mod credentials;
 pub mod download;
 pub mod help;
 pub mod invocation;
+pub mod lockfile;
+pub mod manifest;
 pub mod platform;
+pub mod progress;
+pub mod proxy;
+pub mod signature;
+pub mod store;
 pub mod template;
 pub mod util;
 
 use crate::args::{parse_args, Args};
 use crate::cache::{Cache, CommandNotFoundError};
-use crate::config::{get_config, CONFIG_FILENAME};
+use crate::check::check_configuration;
+use crate::config::{get_config, get_unvalidated_config, Configuration, CONFIG_FILENAME};
 use crate::help::print_help;
 use crate::invocation::run_invocation;
+use crate::lockfile::{get_lockfile_path, write_lockfile, LOCK_FILENAME};
+use crate::progress::format_bytes;
 use anyhow::Context;
 use std::process::exit;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -44,19 +57,84 @@ const EXIT_CODE_NOT_FOUND: i32 = 125;
 
 fn main() -> Result<()> {
     let verbose_env = std::env::var("TOOL_TOOL_VERBOSE").is_ok();
+    let offline_env = std::env::var("TOOL_TOOL_OFFLINE").is_ok();
     let args = parse_args(&mut std::env::args().skip(1), verbose_env)?;
     let binary = std::env::args().next().unwrap();
 
     match args {
         Args::Help => {
-            let configuration = get_config(&binary).unwrap_or_default();
-            print_help(&configuration, &mut std::io::stdout().lock())?;
+            print_help(&get_config(&binary), &mut std::io::stdout().lock())?;
         }
-        Args::Download => {
+        Args::Download {
+            frozen,
+            offline,
+            tool_names,
+        } => {
             VERBOSE.store(false, Ordering::Relaxed);
-            init_cache(&binary)?;
+            init_cache(&binary, frozen, offline || offline_env, |cache| {
+                if tool_names.is_empty() {
+                    cache.init()
+                } else {
+                    cache.init_for_tools(&tool_names)
+                }
+            })?;
             report!("Download complete!");
         }
+        Args::Lock { offline } => {
+            VERBOSE.store(false, Ordering::Relaxed);
+            let mut cache = create_cache(&binary)?;
+            cache.offline = offline || offline_env;
+            let lockfile = cache.lock().context("Could not lock tools")?;
+            let lockfile_path = get_lockfile_path(&cache.configuration)
+                .with_context(|| format!("Unable to determine location of {}", LOCK_FILENAME))?;
+            write_lockfile(&lockfile_path, &lockfile)?;
+            report!("Lockfile written to {}", make_absolute(&lockfile_path)?);
+        }
+        Args::Verify { offline } => {
+            VERBOSE.store(false, Ordering::Relaxed);
+            let mut cache = create_cache(&binary)?;
+            cache.offline = offline || offline_env;
+            let reinstalled = cache.verify().context("Could not verify tools")?;
+            report!("Verification complete, {} tools reinstalled", reinstalled);
+        }
+        Args::Check => {
+            VERBOSE.store(false, Ordering::Relaxed);
+            let configuration = load_configuration(&binary, get_unvalidated_config)?;
+            let configuration_file = configuration
+                .configuration_files
+                .last()
+                .cloned()
+                .unwrap_or_default();
+            let problems = check_configuration(&configuration);
+            if !problems.is_empty() {
+                bail!(
+                    "Found {} problems in {}:\n  {}",
+                    problems.len(),
+                    configuration_file,
+                    problems.join("\n  ")
+                );
+            }
+            report!("No problems found in {}", configuration_file);
+        }
+        Args::Reinstall {
+            tool_names,
+            all,
+            offline,
+        } => {
+            VERBOSE.store(false, Ordering::Relaxed);
+            let mut cache = create_cache(&binary)?;
+            cache.offline = offline || offline_env;
+            cache
+                .reinstall(&tool_names, all)
+                .context("Could not reinstall tools")?;
+            report!("Reinstall complete!");
+        }
+        Args::Clean { all, shared } => {
+            VERBOSE.store(false, Ordering::Relaxed);
+            let cache = create_cache(&binary)?;
+            let reclaimed = cache.clean(all, shared).context("Could not clean tools")?;
+            report!("Reclaimed {}", format_bytes(reclaimed as f64));
+        }
         Args::GetBinaryPath { command_name } => {
             VERBOSE.store(false, Ordering::Relaxed);
             let cache = create_cache(&binary)?;
@@ -90,7 +168,12 @@ fn main() -> Result<()> {
         }
         Args::Invocation(mut invocation) => {
             VERBOSE.store(invocation.verbose, Ordering::Relaxed);
-            let cache = init_cache(&binary)?;
+            let cache = init_cache(
+                &binary,
+                invocation.frozen,
+                invocation.offline || offline_env,
+                |cache| cache.init_for_command(&invocation.command_name),
+            )?;
             let command_result = cache.get_command_line(&invocation.command_name);
             if invocation.from_shim {
                 if let Err(err) = &command_result {
@@ -121,16 +204,31 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn init_cache(binary_name: &str) -> Result<Cache> {
+// Creates the cache and installs tools using the given init function
+fn init_cache(
+    binary_name: &str,
+    frozen: bool,
+    offline: bool,
+    init: impl FnOnce(&mut Cache) -> Result<()>,
+) -> Result<Cache> {
     verbose!("{} {}", NAME, VERSION);
     let mut cache = create_cache(binary_name)?;
-    cache.init().context("Could not initialize cache")?;
+    cache.frozen = frozen;
+    cache.offline = offline;
+    init(&mut cache).context("Could not initialize cache")?;
     verbose!("Cache initialized");
     Ok(cache)
 }
 
+fn load_configuration(
+    binary_name: &str,
+    read: fn(&str) -> Result<Configuration>,
+) -> Result<Configuration> {
+    read(binary_name).with_context(|| format!("Unable to load configuration, please ensure that a file called {} exists, either in the current directory or an ancestor", CONFIG_FILENAME))
+}
+
 fn create_cache(binary_name: &str) -> Result<Cache> {
-    let configuration = get_config(&binary_name).with_context(|| format!("Unable to load configuration, please ensure that a file called {} exists, either in the current directory or an ancestor", CONFIG_FILENAME))?;
+    let configuration = load_configuration(binary_name, get_config)?;
     let cache = Cache::create(configuration)?;
     Ok(cache)
 }
@@ -0,0 +1,147 @@
+use crate::checksum::sha256_file;
+use crate::Result;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+
+// Written into the tool dir as the last step of an installation
+pub const MANIFEST_FILENAME: &str = ".tool-tool.manifest";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub url: String,
+    pub tool_tool_version: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    // path relative to the tool dir, separated by '/'
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Manifest {
+    pub fn create(name: &str, version: &str, url: &str, tool_dir: &Path) -> Result<Manifest> {
+        let mut files = vec![];
+        add_files(tool_dir, "", &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Manifest {
+            name: name.to_string(),
+            version: version.to_string(),
+            url: url.to_string(),
+            tool_tool_version: crate::VERSION.to_string(),
+            files,
+        })
+    }
+
+    // Returns a description of every missing or modified file, files added later are ignored
+    pub fn verify(&self, tool_dir: &Path) -> Result<Vec<String>> {
+        let mut problems = vec![];
+        for file in &self.files {
+            let path = tool_dir.join(&file.path);
+            let metadata = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    problems.push(format!("missing file {}", file.path));
+                    continue;
+                }
+            };
+            if metadata.len() != file.size || sha256_file(&path)? != file.sha256 {
+                problems.push(format!("modified file {}", file.path));
+            }
+        }
+        Ok(problems)
+    }
+}
+
+fn add_files(dir: &Path, prefix: &str, files: &mut Vec<ManifestFile>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Unable to read {:?}", dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative_path = format!("{}{}", prefix, name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            add_files(&entry.path(), &format!("{}/", relative_path), files)?;
+        } else if file_type.is_file() && relative_path != MANIFEST_FILENAME {
+            files.push(ManifestFile {
+                path: relative_path,
+                size: entry.metadata()?.len(),
+                sha256: sha256_file(&entry.path())?,
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn read_manifest(tool_dir: &Path) -> Result<Option<Manifest>> {
+    let path = tool_dir.join(MANIFEST_FILENAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = File::open(&path).with_context(|| format!("Unable to open manifest {:?}", path))?;
+    let manifest = serde_yaml::from_reader(file)
+        .with_context(|| format!("Unable to parse manifest {:?}", path))?;
+    Ok(Some(manifest))
+}
+
+pub fn write_manifest(tool_dir: &Path, manifest: &Manifest) -> Result<()> {
+    let path = tool_dir.join(MANIFEST_FILENAME);
+    let file =
+        File::create(&path).with_context(|| format!("Unable to create manifest {:?}", path))?;
+    serde_yaml::to_writer(file, manifest)
+        .with_context(|| format!("Unable to write manifest {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_and_verify_manifest() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let tool_dir = temp_dir.path();
+        std::fs::create_dir_all(tool_dir.join("bin")).unwrap();
+        std::fs::write(tool_dir.join("bin/foo"), "Hello, World!").unwrap();
+        std::fs::write(tool_dir.join("README"), "readme").unwrap();
+        let manifest =
+            Manifest::create("foo", "1.2.3", "https://example.com/foo.tar.gz", tool_dir).unwrap();
+        write_manifest(tool_dir, &manifest).unwrap();
+        let manifest = read_manifest(tool_dir).unwrap().unwrap();
+        assert_eq!(
+            manifest.files,
+            vec![
+                ManifestFile {
+                    path: "README".to_string(),
+                    size: 6,
+                    sha256: "711a6108ba2ce6ca93dd47d6817f2361db10d8ab6eec89460b2dfc2c325efabe"
+                        .to_string(),
+                },
+                ManifestFile {
+                    path: "bin/foo".to_string(),
+                    size: 13,
+                    sha256: "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+                        .to_string(),
+                },
+            ]
+        );
+        assert_eq!(manifest.verify(tool_dir).unwrap(), Vec::<String>::new());
+
+        std::fs::write(tool_dir.join("bin/foo"), "Hello, Moon!!").unwrap();
+        std::fs::remove_file(tool_dir.join("README")).unwrap();
+        std::fs::write(tool_dir.join("cache"), "added later").unwrap();
+        assert_eq!(
+            manifest.verify(tool_dir).unwrap(),
+            vec!["missing file README", "modified file bin/foo"]
+        );
+        assert_eq!(
+            read_manifest(&tool_dir.join("bin")).unwrap(),
+            None,
+            "no manifest"
+        );
+    }
+}
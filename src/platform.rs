@@ -7,7 +7,11 @@ pub trait PlatformFunctions {
 }
 
 pub trait Platform {
-    fn get_download_url<'a>(&self, tool_configuration: &'a ToolConfiguration) -> Option<&'a str>;
+    fn get_download_urls<'a>(
+        &self,
+        tool_configuration: &'a ToolConfiguration,
+    ) -> Option<&'a [String]>;
+    fn get_sha256<'a>(&self, tool_configuration: &'a ToolConfiguration) -> Option<&'a str>;
     fn get_application_extensions(&self) -> &'static [&'static str];
     fn get_name(&self) -> &'static str;
 }
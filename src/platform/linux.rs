@@ -12,7 +12,10 @@ impl PlatformFunctions for Linux {
 }
 
 impl Platform for Linux {
-    fn get_download_url<'a>(&self, tool_configuration: &'a ToolConfiguration) -> Option<&'a str> {
+    fn get_download_urls<'a>(
+        &self,
+        tool_configuration: &'a ToolConfiguration,
+    ) -> Option<&'a [String]> {
         tool_configuration
             .download
             .linux
@@ -20,6 +23,10 @@ impl Platform for Linux {
             .or_else(|| tool_configuration.download.default.as_deref())
     }
 
+    fn get_sha256<'a>(&self, tool_configuration: &'a ToolConfiguration) -> Option<&'a str> {
+        tool_configuration.sha256.get(self.get_name())
+    }
+
     fn get_application_extensions(&self) -> &'static [&'static str] {
         &["", ".sh"]
     }
@@ -20,7 +20,10 @@ impl crate::platform::PlatformFunctions for Windows {
 }
 
 impl Platform for Windows {
-    fn get_download_url<'a>(&self, tool_configuration: &'a ToolConfiguration) -> Option<&'a str> {
+    fn get_download_urls<'a>(
+        &self,
+        tool_configuration: &'a ToolConfiguration,
+    ) -> Option<&'a [String]> {
         tool_configuration
             .download
             .windows
@@ -28,6 +31,10 @@ impl Platform for Windows {
             .or_else(|| tool_configuration.download.default.as_deref())
     }
 
+    fn get_sha256<'a>(&self, tool_configuration: &'a ToolConfiguration) -> Option<&'a str> {
+        tool_configuration.sha256.get(self.get_name())
+    }
+
     fn get_application_extensions(&self) -> &'static [&'static str] {
         &[".exe", ".cmd", ".bat", ""]
     }
@@ -0,0 +1,124 @@
+use crate::download::DownloadProgress;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+// Interval between updates of the progress line on a terminal
+const INTERACTIVE_INTERVAL: Duration = Duration::from_millis(100);
+// Interval between progress lines in non-interactive output, e.g. CI logs
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct ProgressReporter {
+    label: String,
+    // update a single line in place instead of printing periodic lines
+    interactive: bool,
+    last_report: Instant,
+    line_length: usize,
+}
+
+impl ProgressReporter {
+    pub fn new(label: String, allow_interactive: bool) -> Self {
+        ProgressReporter {
+            label,
+            interactive: allow_interactive && std::io::stderr().is_terminal(),
+            last_report: Instant::now(),
+            line_length: 0,
+        }
+    }
+
+    pub fn update(&mut self, progress: &DownloadProgress) {
+        let interval = if self.interactive {
+            INTERACTIVE_INTERVAL
+        } else {
+            PLAIN_INTERVAL
+        };
+        if self.last_report.elapsed() < interval {
+            return;
+        }
+        self.last_report = Instant::now();
+        let line = format!("{}: {}", self.label, format_progress(progress));
+        if self.interactive {
+            let padding = self.line_length.saturating_sub(line.chars().count());
+            eprint!("\r🔧 ⏳ {}{}", line, " ".repeat(padding));
+            let _ = std::io::stderr().flush();
+            self.line_length = line.chars().count();
+        } else {
+            report!("⏳ {}", line);
+        }
+    }
+
+    // Clear the progress line, so that it can be overwritten by the final status
+    pub fn finish(&mut self) {
+        if self.interactive && self.line_length > 0 {
+            eprint!("\r{}\r", " ".repeat(self.line_length + 6));
+            let _ = std::io::stderr().flush();
+            self.line_length = 0;
+        }
+    }
+}
+
+fn format_progress(progress: &DownloadProgress) -> String {
+    let received = format_bytes(progress.received as f64);
+    let rate = format_bytes(progress.bytes_per_second);
+    match progress.total {
+        Some(total) if total > 0 => format!(
+            "{} of {} ({}%, {}/s)",
+            received,
+            format_bytes(total as f64),
+            progress.received * 100 / total,
+            rate
+        ),
+        _ => format!("{} ({}/s)", received, rate),
+    }
+}
+
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024.0 {
+        return format!("{} B", bytes as u64);
+    }
+    let mut value = bytes / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes() {
+        assert_eq!(format_bytes(0.0), "0 B");
+        assert_eq!(format_bytes(1023.0), "1023 B");
+        assert_eq!(format_bytes(1024.0), "1.0 KiB");
+        assert_eq!(format_bytes(1536.0), "1.5 KiB");
+        assert_eq!(format_bytes(300.0 * 1024.0 * 1024.0), "300.0 MiB");
+        assert_eq!(format_bytes(5.0 * 1024.0 * 1024.0 * 1024.0), "5.0 GiB");
+    }
+
+    #[test]
+    fn progress_with_total() {
+        let progress = DownloadProgress {
+            received: 12 * 1024 * 1024,
+            total: Some(300 * 1024 * 1024),
+            bytes_per_second: 4.0 * 1024.0 * 1024.0,
+        };
+        assert_eq!(
+            format_progress(&progress),
+            "12.0 MiB of 300.0 MiB (4%, 4.0 MiB/s)"
+        );
+    }
+
+    #[test]
+    fn progress_without_total() {
+        let progress = DownloadProgress {
+            received: 2048,
+            total: None,
+            bytes_per_second: 512.0,
+        };
+        assert_eq!(format_progress(&progress), "2.0 KiB (512 B/s)");
+    }
+}
@@ -0,0 +1,77 @@
+use crate::Result;
+use anyhow::Context;
+use minisign_verify::{PublicKey, Signature};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Verify a minisign signature of the file at path against the trusted public key
+pub fn verify_signature(path: &Path, signature: &str, public_key: &str) -> Result<()> {
+    let public_key = PublicKey::from_base64(public_key.trim())
+        .with_context(|| format!("Invalid public key '{}'", public_key.trim()))?;
+    let signature = Signature::decode(signature).context("Invalid signature")?;
+    let mut verifier = public_key
+        .verify_stream(&signature)
+        .context("Signature verification failed")?;
+    let mut file = File::open(path)
+        .with_context(|| format!("Unable to open file {:?} for verification", path))?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let count = file
+            .read(&mut buffer)
+            .with_context(|| format!("Unable to read file {:?} for verification", path))?;
+        if count == 0 {
+            break;
+        }
+        verifier.update(&buffer[..count]);
+    }
+    verifier
+        .finalize()
+        .context("Signature verification failed")?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const PUBLIC_KEY: &str = "RWQBAgMEBQYHCAOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4";
+    // Signature of "Hello, World!" made with the secret key belonging to PUBLIC_KEY
+    pub(crate) const HELLO_WORLD_SIGNATURE: &str =
+        "untrusted comment: signature from minisign secret key
+RUQBAgMEBQYHCOuoH9yzbqCdXm6NTnhYIcZENH1nyTmu/Iw8HlK57l87ikxE0ExcZBKh9dfaPoXKUrf45skYU/Uek6m3L4iAjgU=
+trusted comment: timestamp:1600000000\tfile:foo
+z0trMl1e49wwq6TRstIrG2KT4NKzj+zsrHSYUjNMev4XcGuQT0NKcp3kNbj098BX9smIr867KFr0576g3eD4Ag==
+";
+
+    fn create_temp_file(content: &[u8]) -> tempfile::TempPath {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), content).unwrap();
+        file.into_temp_path()
+    }
+
+    #[test]
+    fn valid_signature() {
+        let path = create_temp_file(b"Hello, World!");
+        verify_signature(&path, HELLO_WORLD_SIGNATURE, PUBLIC_KEY).unwrap();
+    }
+
+    #[test]
+    fn invalid_signature() {
+        let path = create_temp_file(b"Hello, World?");
+        let error = verify_signature(&path, HELLO_WORLD_SIGNATURE, PUBLIC_KEY)
+            .expect_err("Signature should not match");
+        assert_eq!(
+            format!("{:#}", error),
+            "Signature verification failed: The signature verification failed"
+        );
+    }
+
+    #[test]
+    fn invalid_public_key() {
+        let path = create_temp_file(b"Hello, World!");
+        let error = verify_signature(&path, HELLO_WORLD_SIGNATURE, "RWQ")
+            .expect_err("Public key should be invalid");
+        assert_eq!(error.to_string(), "Invalid public key 'RWQ'");
+    }
+}
@@ -0,0 +1,226 @@
+use crate::checksum::sha256_hex;
+use crate::util::{find_tmp_dirs, lock_tmp_dir, remove_tmp_dir};
+use crate::Result;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+const TMP_DIR_PREFIX: &str = ".tmp-";
+
+// Extracted tools shared between all projects of a user, keyed by the content of the download.
+// Entries are separate read-only copies, project tool directories are populated with hardlinks
+// to them on unix, so a tool modifying its own files cannot corrupt the shared copy, and with
+// copies elsewhere.
+pub struct SharedStore {
+    root: PathBuf,
+}
+
+impl SharedStore {
+    pub fn new(root: PathBuf) -> Self {
+        SharedStore { root }
+    }
+
+    pub fn default_location() -> Option<PathBuf> {
+        dirs::cache_dir().map(|cache_dir| cache_dir.join("tool-tool").join("v1"))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // The extracted content depends on how the archive is unpacked, not only on its bytes
+    pub fn get_key(
+        sha256: &str,
+        format: &str,
+        strip_directories: usize,
+        name: &str,
+        version: &str,
+    ) -> String {
+        sha256_hex(
+            format!(
+                "{}\n{}\n{}\n{}\n{}",
+                sha256.trim().to_lowercase(),
+                format,
+                strip_directories,
+                name,
+                version
+            )
+            .as_bytes(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        let entry_dir = self.get_entry_dir(key);
+        if entry_dir.exists() {
+            Some(entry_dir)
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&self, key: &str, tool_dir: &Path) -> Result<()> {
+        let entry_dir = self.get_entry_dir(key);
+        if entry_dir.exists() {
+            return Ok(());
+        }
+        let tmp_dir = self.root.join("tools").join(format!(
+            "{}{}-{}",
+            TMP_DIR_PREFIX,
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_nanos(),
+            std::process::id()
+        ));
+        let lock = lock_tmp_dir(&tmp_dir)?;
+        // copied, so that the files of the project the tool was downloaded for stay writable
+        copy_tree(tool_dir, &tmp_dir)?;
+        make_read_only(&tmp_dir)?;
+        // another process may have added the same entry in the meantime, then the copy is
+        // removed along with the lock file
+        let _ = std::fs::rename(&tmp_dir, &entry_dir);
+        remove_tmp_dir(&tmp_dir, lock)
+    }
+
+    // Dirs of insertions, which are orphaned if their lock is not held
+    pub fn get_tmp_dirs(&self) -> Result<Vec<PathBuf>> {
+        find_tmp_dirs(&self.root.join("tools"), &|name| {
+            name.starts_with(TMP_DIR_PREFIX)
+        })
+    }
+
+    fn get_entry_dir(&self, key: &str) -> PathBuf {
+        self.root.join("tools").join(key)
+    }
+}
+
+// Recreate the directory tree at target, hardlinking files where possible
+pub fn link_tree(source: &Path, target: &Path) -> Result<()> {
+    transfer_tree(source, target, &link_file)
+}
+
+fn copy_tree(source: &Path, target: &Path) -> Result<()> {
+    transfer_tree(source, target, &copy_file)
+}
+
+fn transfer_tree(
+    source: &Path,
+    target: &Path,
+    transfer_file: &dyn Fn(&Path, &Path) -> Result<()>,
+) -> Result<()> {
+    std::fs::create_dir_all(target)
+        .with_context(|| format!("Unable to create directory {:?}", target))?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let source_path = entry.path();
+        let target_path = target.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            transfer_tree(&source_path, &target_path, transfer_file)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&source_path, &target_path)?;
+        } else {
+            transfer_file(&source_path, &target_path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn link_file(source: &Path, target: &Path) -> Result<()> {
+    if std::fs::hard_link(source, target).is_err() {
+        copy_file(source, target)?;
+    }
+    Ok(())
+}
+
+// Read-only files cannot be removed on windows, so tools are copied instead
+#[cfg(not(unix))]
+fn link_file(source: &Path, target: &Path) -> Result<()> {
+    copy_file(source, target)
+}
+
+fn copy_file(source: &Path, target: &Path) -> Result<()> {
+    std::fs::copy(source, target)
+        .with_context(|| format!("Unable to copy {:?} to {:?}", source, target))?;
+    Ok(())
+}
+
+// Remove write permissions from all files, directories stay writable so entries can be removed
+#[cfg(unix)]
+fn make_read_only(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            make_read_only(&entry.path())?;
+        } else if file_type.is_file() {
+            let mut permissions = entry.metadata()?.permissions();
+            permissions.set_mode(permissions.mode() & !0o222);
+            std::fs::set_permissions(entry.path(), permissions)
+                .with_context(|| format!("Unable to make {:?} read-only", entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_read_only(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, target: &Path) -> Result<()> {
+    let link_target = std::fs::read_link(source)?;
+    std::os::unix::fs::symlink(&link_target, target)
+        .with_context(|| format!("Unable to create symlink {:?}", target))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, target: &Path) -> Result<()> {
+    std::fs::copy(source, target)
+        .with_context(|| format!("Unable to copy {:?} to {:?}", source, target))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_link_tool() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = SharedStore::new(temp_dir.path().join("store"));
+        let tool_dir = temp_dir.path().join("tool");
+        std::fs::create_dir_all(tool_dir.join("bin")).unwrap();
+        std::fs::write(tool_dir.join("bin/tool"), "tool").unwrap();
+        let key = SharedStore::get_key("ABC", "tar.gz", 1, "tool", "1.0");
+        assert_eq!(key, SharedStore::get_key("abc", "tar.gz", 1, "tool", "1.0"));
+        assert_ne!(key, SharedStore::get_key("abc", "tar.gz", 0, "tool", "1.0"));
+        assert_ne!(key, SharedStore::get_key("abc", "tar.gz", 1, "tool", "2.0"));
+        assert_eq!(store.get(&key), None);
+
+        store.insert(&key, &tool_dir).unwrap();
+        let entry_dir = store.get(&key).unwrap();
+        #[cfg(unix)]
+        assert!(std::fs::metadata(entry_dir.join("bin/tool"))
+            .unwrap()
+            .permissions()
+            .readonly());
+        // the entry is a copy, the original files are not made read-only
+        std::fs::write(tool_dir.join("bin/tool"), "modified").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(entry_dir.join("bin/tool")).unwrap(),
+            "tool"
+        );
+        let linked_dir = temp_dir.path().join("linked");
+        link_tree(&entry_dir, &linked_dir).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(linked_dir.join("bin/tool")).unwrap(),
+            "tool"
+        );
+        // inserting an existing entry keeps it
+        store.insert(&key, &tool_dir).unwrap();
+        assert_eq!(store.get(&key), Some(entry_dir));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{bail, Result};
 use anyhow::Context;
+use std::cell::RefCell;
 
 pub fn template<F: Fn(&str) -> Result<String>>(string: &str, replacer: F) -> Result<String> {
     //    replacer(string)
@@ -29,6 +30,16 @@ pub fn template<F: Fn(&str) -> Result<String>>(string: &str, replacer: F) -> Res
     Ok(result)
 }
 
+// Names of all templates in the string, e.g. "cmd:foo" for "${cmd:foo}"
+pub fn get_template_names(string: &str) -> Result<Vec<String>> {
+    let names = RefCell::new(vec![]);
+    template(string, |name| {
+        names.borrow_mut().push(name.to_string());
+        Ok(String::new())
+    })?;
+    Ok(names.into_inner())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,4 +90,13 @@ mod tests {
             "Unclosed template string in template '${foo', you may be missing a closing '}'"
         );
     }
+
+    #[test]
+    fn template_names() {
+        assert_eq!(
+            get_template_names("${dir:foo}/bin ${cmd:bar} x").unwrap(),
+            vec!["dir:foo", "cmd:bar"]
+        );
+        assert_eq!(get_template_names("plain").unwrap(), Vec::<String>::new());
+    }
 }
@@ -1,6 +1,13 @@
-use crate::Result;
+use crate::{bail, Result};
 use anyhow::Context;
-use std::time::Duration;
+use fs2::FileExt;
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const TMP_DIR_LOCK_SUFFIX: &str = ".lock";
 
 // NOTE: under windows, file operations often fail spuriously, most likely due to virus scanners
 // keeping files open
@@ -45,10 +52,159 @@ where
     }
 }
 
+// Take an exclusive lock on the file, which is released when the returned file is dropped.
+// Waits for other processes holding the lock until the timeout expires.
+pub fn lock_file(path: &Path, timeout: Duration, on_wait: &mut dyn FnMut()) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Unable to open lock file {:?}", path))?;
+    let start = Instant::now();
+    let mut waiting = false;
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(file),
+            Err(error) if error.kind() == fs2::lock_contended_error().kind() => {
+                if !waiting {
+                    waiting = true;
+                    on_wait();
+                }
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("Unable to lock file {:?}", path))
+            }
+        }
+        if start.elapsed() >= timeout {
+            bail!(
+                "Timed out after {:.1}s waiting for the lock on {:?}",
+                timeout.as_secs_f64(),
+                path
+            );
+        }
+        std::thread::sleep(LOCK_POLL_INTERVAL);
+    }
+}
+
+// Take an exclusive lock on the file without waiting, returns None if another process holds it
+pub fn try_lock_file(path: &Path) -> Result<Option<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Unable to open lock file {:?}", path))?;
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(file)),
+        Err(error) if error.kind() == fs2::lock_contended_error().kind() => Ok(None),
+        Err(error) => Err(error).with_context(|| format!("Unable to lock file {:?}", path)),
+    }
+}
+
+// Lock file next to a temp dir, which is held by the process using the dir, so that cleaning up
+// can tell the dirs of crashed processes from dirs in use
+pub fn get_tmp_dir_lock_path(dir: &Path) -> PathBuf {
+    let mut path = dir.as_os_str().to_owned();
+    path.push(TMP_DIR_LOCK_SUFFIX);
+    PathBuf::from(path)
+}
+
+// Lock a new temp dir before creating it, the lock is released when the returned file is dropped
+pub fn lock_tmp_dir(dir: &Path) -> Result<File> {
+    std::fs::create_dir_all(dir.parent().expect("Parent should exist"))?;
+    try_lock_file(&get_tmp_dir_lock_path(dir))?
+        .with_context(|| format!("Temp dir {:?} is already in use", dir))
+}
+
+// Remove the temp dir and its lock file, while still holding the lock
+pub fn remove_tmp_dir(dir: &Path, lock: File) -> Result<()> {
+    if dir.exists() {
+        retry(|| std::fs::remove_dir_all(dir))
+            .with_context(|| format!("Could not remove temp dir {:?}", dir))?;
+    }
+    let lock_path = get_tmp_dir_lock_path(dir);
+    retry(|| std::fs::remove_file(&lock_path))
+        .with_context(|| format!("Could not remove lock file {:?}", lock_path))?;
+    drop(lock);
+    Ok(())
+}
+
+// Temp dirs in parent with a matching name, including dirs of which only the lock file is left
+pub fn find_tmp_dirs(parent: &Path, is_tmp_dir: &dyn Fn(&str) -> bool) -> Result<Vec<PathBuf>> {
+    let mut dirs = BTreeSet::new();
+    if !parent.exists() {
+        return Ok(vec![]);
+    }
+    for entry in std::fs::read_dir(parent)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        let name = name.strip_suffix(TMP_DIR_LOCK_SUFFIX).unwrap_or(&name);
+        if is_tmp_dir(name) {
+            dirs.insert(parent.join(name));
+        }
+    }
+    Ok(dirs.into_iter().collect())
+}
+
+// Standard base64 encoding with padding, as used for HTTP basic authentication
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Decode %XX escapes in urls, invalid escapes are kept as is
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"user:p@ss"), "dXNlcjpwQHNz");
+    }
+
     #[test]
     fn retry_success() -> Result<()> {
         let res = retry::<_, std::io::Error, _>(|| Ok(42))?;
@@ -56,6 +212,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn lock_file_waits_for_other_lock() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("tool.lock");
+        let lock = lock_file(&path, Duration::from_secs(1), &mut || {
+            panic!("lock should not be contended")
+        })
+        .unwrap();
+        let mut waited = 0;
+        let error = lock_file(&path, Duration::from_millis(200), &mut || waited += 1).unwrap_err();
+        assert_eq!(waited, 1);
+        assert_eq!(
+            error.to_string(),
+            format!("Timed out after 0.2s waiting for the lock on {:?}", path)
+        );
+        drop(lock);
+        lock_file(&path, Duration::from_millis(200), &mut || {
+            panic!("lock should have been released")
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn try_lock_file_without_waiting() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("tool.lock");
+        let lock = try_lock_file(&path).unwrap().expect("lock should be free");
+        assert!(try_lock_file(&path).unwrap().is_none());
+        drop(lock);
+        assert!(try_lock_file(&path).unwrap().is_some());
+    }
+
     #[test]
     fn retry_failure() -> Result<()> {
         let res = retry::<u32, _, _>(|| Err(std::env::VarError::NotPresent));